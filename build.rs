@@ -1,6 +1,12 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
-        .build_server(true) // Build the server to use the gen code in a stub for integration tests
+        // The generated server backs the simulator, the proxy and the daemon
+        .build_server(true)
+        // Lets sessions recorded by the proxy store requests and replies
+        .type_attribute(
+            ".api",
+            "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]",
+        )
         .compile(&["proto/keymapp.proto"], &["proto"])?;
     Ok(())
 }
@@ -1,4 +1,4 @@
-use kontroll::{utils, Kontroll};
+use kontroll::{blocking::Kontroll, utils, RetryPolicy};
 use macroquad::prelude::*;
 use std::collections::LinkedList;
 
@@ -13,12 +13,9 @@ struct Snake {
 }
 
 #[macroquad::main("Snake")]
-#[tokio::main]
 async fn main() {
     // Macroquad is single-threaded by using a custom async executor calling next_frame().await
-    // Therefore, we use tokio's runtime and block the thread in order to use the async api
-    let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
-
+    // Therefore, we use the blocking api which runs its own tokio runtime
     const WIDTH: i16 = 12;
     const HEIGHT: i16 = 4;
 
@@ -39,7 +36,10 @@ async fn main() {
     let right = (1, 0);
     let left = (-1, 0);
 
-    let api = runtime.block_on(Kontroll::new(None)).unwrap();
+    // Keep the game running if Keymapp restarts while playing
+    let api =
+        Kontroll::from_builder(kontroll::Kontroll::builder().retry_policy(RetryPolicy::default()))
+            .unwrap();
 
     loop {
         if !game_over {
@@ -87,7 +87,7 @@ async fn main() {
                     }
                 }
                 navigation_lock = false;
-                let _ = runtime.block_on(api.set_rgb_all(0, 0, 0, 0));
+                let _ = api.set_rgb_all(0, 0, 0, 0);
             }
         }
         if !game_over {
@@ -138,13 +138,13 @@ async fn main() {
                 DARKGREEN,
             );
 
-            let _ = runtime.block_on(api.set_rgb_led(
+            let _ = api.set_rgb_led(
                 utils::pos_to_voyager(snake.head.0 as u16, snake.head.1 as u16),
                 255,
                 0,
                 0,
                 0,
-            ));
+            );
 
             for (x, y) in &snake.body {
                 draw_rectangle(
@@ -155,13 +155,7 @@ async fn main() {
                     LIME,
                 );
 
-                let _ = runtime.block_on(api.set_rgb_led(
-                    utils::pos_to_voyager(*x as u16, *y as u16),
-                    80,
-                    0,
-                    0,
-                    0,
-                ));
+                let _ = api.set_rgb_led(utils::pos_to_voyager(*x as u16, *y as u16), 80, 0, 0, 0);
             }
 
             draw_rectangle(
@@ -172,13 +166,13 @@ async fn main() {
                 GOLD,
             );
 
-            let _ = runtime.block_on(api.set_rgb_led(
+            let _ = api.set_rgb_led(
                 utils::pos_to_voyager(fruit.0 as u16, fruit.1 as u16),
                 255,
                 255,
                 224,
                 0,
-            ));
+            );
 
             draw_text(format!("SCORE: {score}").as_str(), 10., 20., 20., DARKGRAY);
         } else {
@@ -197,7 +191,7 @@ async fn main() {
 
             if get_time() - last_update > speed {
                 last_update = get_time();
-                let _ = runtime.block_on(api.restore_rgb_leds());
+                let _ = api.restore_rgb_leds();
             }
 
             if is_key_down(KeyCode::Enter) {
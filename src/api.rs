@@ -1,37 +1,224 @@
-use serde::Serialize;
 use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-/// Generated code from the proto file
-use keymapp::{
-    ConnectAnyKeyboardRequest, ConnectKeyboardRequest, DisconnectKeyboardRequest,
-    GetKeyboardsRequest,
-};
+use futures_core::Stream;
+use tokio::time::MissedTickBehavior;
 
-#[cfg(not(target_os = "windows"))]
-use tokio::net::UnixStream;
+use crate::backend::{GrpcBackend, KeyboardBackend};
+use crate::keyboard::{self, KeyboardModel};
+use crate::retry::{self, ConnectionEvent, RetryPolicy};
+use crate::transport::Transport;
+use crate::watch::{self, StatusEvent};
 
-use tonic::Request;
-#[cfg(not(target_os = "windows"))]
-use tonic::transport::{
-    Endpoint, Uri
-};
-#[cfg(not(target_os = "windows"))]
-use tower::service_fn;
+/// Generated code from the proto file
+use keymapp::ConnectKeyboardRequest;
 
+/// Errors returned by the Kontroll API.
+///
+/// Transport errors ([`ApiError::is_transport`]) are usually worth retrying, as they happen
+/// when Keymapp is not running or restarting. Other errors won't go away by retrying the call.
 #[derive(Debug)]
-pub struct ApiError {
-    message: String,
+pub enum ApiError {
+    /// The config directory could not be determined, so the Keymapp socket can't be located.
+    ConfigDirNotFound,
+    /// No Keymapp socket exists at the given path.
+    SocketNotFound(PathBuf),
+    /// The endpoint used to reach Keymapp is invalid.
+    InvalidEndpoint(tonic::transport::Error),
+    /// The connection to Keymapp could not be established.
+    Connect(tonic::transport::Error),
+    /// Keymapp did not answer in time.
+    Timeout {
+        operation: Operation,
+        duration: Duration,
+    },
+    /// A call to Keymapp failed, the status carries the gRPC code returned.
+    Rpc {
+        operation: Operation,
+        status: Box<tonic::Status>,
+    },
+    /// Keymapp answered a call with `success: false`, only returned in strict mode.
+    Rejected { operation: Operation },
+    /// An argument passed to the API is out of range.
+    InvalidArgument(String),
+}
+
+impl ApiError {
+    fn rpc(operation: Operation) -> impl FnOnce(tonic::Status) -> Self {
+        move |status| ApiError::Rpc {
+            operation,
+            status: Box::new(status),
+        }
+    }
+
+    /// Returns the gRPC code of a failed call.
+    pub fn code(&self) -> Option<tonic::Code> {
+        match self {
+            ApiError::Rpc { status, .. } => Some(status.code()),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the error was caused by the connection to Keymapp rather than by the
+    /// call itself.
+    pub fn is_transport(&self) -> bool {
+        match self {
+            ApiError::SocketNotFound(_) | ApiError::Connect(_) | ApiError::Timeout { .. } => true,
+            ApiError::Rpc { status, .. } => retry::is_transport_status(status),
+            _ => false,
+        }
+    }
+
+    // Whether the call failed before reaching Keymapp.
+    fn is_unsent(&self) -> bool {
+        match self {
+            ApiError::SocketNotFound(_) | ApiError::Connect(_) => true,
+            ApiError::Rpc { status, .. } => retry::is_unsent_code(status.code()),
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+        match self {
+            ApiError::ConfigDirNotFound => write!(f, "Failed to get config directory"),
+            ApiError::SocketNotFound(path) => write!(
+                f,
+                "Keymapp socket not found at {}, make sure Keymapp is running and the API is started.",
+                path.display()
+            ),
+            ApiError::InvalidEndpoint(e) => write!(f, "Failed to create api client: {}", e),
+            ApiError::Connect(e) => write!(f, "Failed to connect to keymapp: {}", e),
+            ApiError::Timeout {
+                operation,
+                duration,
+            } => write!(
+                f,
+                "Timed out after {}ms trying to {}, make sure Keymapp is running and the API is started.",
+                duration.as_millis(),
+                operation
+            ),
+            ApiError::Rpc { operation, status } => {
+                write!(f, "Failed to {}: {}", operation, status.message())
+            }
+            ApiError::Rejected { operation } => write!(f, "Keymapp refused to {}", operation),
+            ApiError::InvalidArgument(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::InvalidEndpoint(e) | ApiError::Connect(e) => Some(e),
+            ApiError::Rpc { status, .. } => Some(status.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A call made to Keymapp along with its arguments, used to describe which call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    OpenConnection,
+    GetStatus,
+    GetKeyboards,
+    Connect {
+        index: usize,
+    },
+    ConnectAny,
+    Disconnect,
+    SetLayer {
+        index: usize,
+    },
+    UnsetLayer {
+        index: usize,
+    },
+    SetRgbLed {
+        led: usize,
+        r: u8,
+        g: u8,
+        b: u8,
+        sustain: i32,
+    },
+    SetRgbAll {
+        r: u8,
+        g: u8,
+        b: u8,
+        sustain: i32,
+    },
+    RestoreRgbLeds,
+    SetStatusLed {
+        led: usize,
+        on: bool,
+        sustain: i32,
+    },
+    RestoreStatusLeds,
+    IncreaseBrightness,
+    DecreaseBrightness,
+}
+
+impl Operation {
+    /// Whether applying the operation twice has the same effect as applying it once, so it can
+    /// be retried when Keymapp may have received it.
+    fn is_idempotent(&self) -> bool {
+        !matches!(
+            self,
+            Operation::IncreaseBrightness | Operation::DecreaseBrightness
+        )
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::OpenConnection => write!(f, "connect to keymapp"),
+            Operation::GetStatus => write!(f, "get status"),
+            Operation::GetKeyboards => write!(f, "get keyboards"),
+            Operation::Connect { index } => write!(f, "connect to keyboard {}", index),
+            Operation::ConnectAny => write!(f, "connect to any keyboard"),
+            Operation::Disconnect => write!(f, "disconnect"),
+            Operation::SetLayer { index } => write!(f, "set layer {}", index),
+            Operation::UnsetLayer { index } => write!(f, "unset layer {}", index),
+            Operation::SetRgbLed {
+                led,
+                r,
+                g,
+                b,
+                sustain,
+            } => write!(
+                f,
+                "set rgb led {} to #{:02x}{:02x}{:02x} (sustain {})",
+                led, r, g, b, sustain
+            ),
+            Operation::SetRgbAll { r, g, b, sustain } => write!(
+                f,
+                "set all rgb leds to #{:02x}{:02x}{:02x} (sustain {})",
+                r, g, b, sustain
+            ),
+            Operation::RestoreRgbLeds => write!(f, "restore rgb leds"),
+            Operation::SetStatusLed { led, on, sustain } => write!(
+                f,
+                "turn status led {} {} (sustain {})",
+                led,
+                if *on { "on" } else { "off" },
+                sustain
+            ),
+            Operation::RestoreStatusLeds => write!(f, "restore status leds"),
+            Operation::IncreaseBrightness => write!(f, "increase brightness"),
+            Operation::DecreaseBrightness => write!(f, "decrease brightness"),
+        }
     }
 }
 
 use self::keymapp::{
     keyboard_service_client::KeyboardServiceClient, Keyboard, SetLayerRequest, SetRgbAllRequest,
-    SetRgbLedRequest,
+    SetRgbLedRequest, SetStatusLedRequest,
 };
 
 /// Generated data structures from the proto file
@@ -39,12 +226,22 @@ pub mod keymapp {
     tonic::include_proto!("api");
 }
 
+type ConnectionListener = Box<dyn Fn(ConnectionEvent) + Send + Sync>;
+
 /// The kontroll API.
 pub struct Kontroll {
-    client: KeyboardServiceClient<tonic::transport::Channel>,
+    backend: Arc<dyn KeyboardBackend>,
+    connected: AtomicBool,
+    // Layers set through `push_layer`, in the order they were pushed.
+    layers: Mutex<Vec<usize>>,
+    strict: bool,
+    request_timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    listener: Option<ConnectionListener>,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 /// Data representation of a connected keyboard, used in the status response
 pub struct ConnectedKeyboard {
     friendly_name: String,
@@ -52,7 +249,8 @@ pub struct ConnectedKeyboard {
     current_layer: i32,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 /// Data representation of the status response, including the version of Kontroll and Keymapp and optionally the connected keyboard.
 pub struct Status {
     keymapp_version: String,
@@ -60,6 +258,56 @@ pub struct Status {
     keyboard: Option<ConnectedKeyboard>,
 }
 
+impl ConnectedKeyboard {
+    pub fn friendly_name(&self) -> &str {
+        &self.friendly_name
+    }
+
+    pub fn firmware_version(&self) -> &str {
+        &self.firmware_version
+    }
+
+    pub fn current_layer(&self) -> i32 {
+        self.current_layer
+    }
+
+    /// Returns the model of the keyboard, with the layout of its LEDs, `None` for keyboards
+    /// Kontroll doesn't know.
+    pub fn model(&self) -> Option<&'static KeyboardModel> {
+        keyboard::from_friendly_name(&self.friendly_name)
+    }
+}
+
+impl Status {
+    pub fn keymapp_version(&self) -> &str {
+        &self.keymapp_version
+    }
+
+    pub fn kontroll_version(&self) -> &str {
+        &self.kontroll_version
+    }
+
+    /// Returns the connected keyboard, if any.
+    pub fn keyboard(&self) -> Option<&ConnectedKeyboard> {
+        self.keyboard.as_ref()
+    }
+}
+
+impl From<keymapp::GetStatusReply> for Status {
+    fn from(reply: keymapp::GetStatusReply) -> Self {
+        let keyboard = reply.connected_keyboard.map(|k| ConnectedKeyboard {
+            friendly_name: k.friendly_name,
+            firmware_version: k.firmware_version,
+            current_layer: k.current_layer,
+        });
+        Status {
+            keymapp_version: reply.keymapp_version,
+            kontroll_version: env!("CARGO_PKG_VERSION").to_string(),
+            keyboard,
+        }
+    }
+}
+
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let keyboard = match &self.keyboard {
@@ -77,174 +325,455 @@ impl fmt::Display for Status {
     }
 }
 
-#[cfg(not(target_os = "windows"))]
+/// The default time allowed to connect to Keymapp.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The shortest time between two polls of [`Kontroll::watch_status`].
+const MIN_WATCH_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Runs a future, failing with [`ApiError::Timeout`] if it doesn't complete in time.
+async fn with_timeout<T, F>(
+    operation: Operation,
+    timeout: Option<Duration>,
+    future: F,
+) -> Result<T, ApiError>
+where
+    F: Future<Output = Result<T, ApiError>>,
+{
+    match timeout {
+        Some(duration) => {
+            tokio::time::timeout(duration, future)
+                .await
+                .map_err(|_| ApiError::Timeout {
+                    operation,
+                    duration,
+                })?
+        }
+        None => future.await,
+    }
+}
+
 /// The get client function handles the connection to Keymapp, on Unix systems it uses a Unix domain socket, on Windows it uses a TCP connection.
 pub async fn get_client(
-    path: Option<String>,
+    port: Option<String>,
 ) -> Result<KeyboardServiceClient<tonic::transport::Channel>, ApiError> {
-    // Get socket path from the supplied path provided, or environment variable or set a default
-
-    let socket_path = match path {
-        Some(p) => std::path::PathBuf::from(p),
-        None => match std::env::var("KEYMAPP_SOCKET") {
-            Ok(p) => std::path::PathBuf::from(p),
-            Err(_) => {
-                let dirs = match directories::BaseDirs::new() {
-                    Some(dirs) => dirs,
-                    None => {
-                        return Err(ApiError {
-                            message: "Failed to get config directory".to_string(),
-                        })
-                    }
-                };
-                let mut p = dirs.config_dir().join(".keymapp/").join("keymapp.sock");
-                if !p.exists() {
-                    // On MacOS, with the Keymapp app store version, the socket is sandboxed and
-                    // located in the app's container directory.
-                    p = dirs.home_dir().join("Library/Containers/io.zsa.keymapp/Data/Library/Application Support/.keymapp/keymapp.sock");
-                }
-                p
-            }
-        },
-    };
+    let transport = Transport::platform_default(port)?;
+    connect_client(&transport, Some(DEFAULT_CONNECT_TIMEOUT)).await
+}
 
-    if !socket_path.exists() {
-        return Err(ApiError { message: format!("Keymapp socket not found at {}, make sure Keymapp is running and the API is started.", socket_path.to_str().unwrap()) });
-    }
+/// Connects to Keymapp over the given transport, giving up after `timeout` if Keymapp doesn't
+/// accept the connection.
+pub async fn connect_client(
+    transport: &Transport,
+    timeout: Option<Duration>,
+) -> Result<KeyboardServiceClient<tonic::transport::Channel>, ApiError> {
+    let channel = with_timeout(Operation::OpenConnection, timeout, transport.connect()).await?;
+    Ok(KeyboardServiceClient::new(channel))
+}
 
-    let channel = Endpoint::try_from("http://[::]:50051")
-        .map_err(|e| ApiError {
-            message: format!("Failed to create api client: {}", e),
-        })?
-        .connect_with_connector(service_fn(move |_: Uri| {
-            UnixStream::connect(socket_path.clone())
-        }))
-        .await
-        .map_err(|e| ApiError {
-            message: format!("Failed to connect to keymapp: {}", e),
-        })?;
+/// Configures and connects a [`Kontroll`] instance.
+///
+/// ```rust,no_run
+/// # async fn run() -> Result<(), kontroll::ApiError> {
+/// use std::time::Duration;
+/// use kontroll::{Kontroll, RetryPolicy};
+///
+/// let api = Kontroll::builder()
+///     .request_timeout(Duration::from_millis(500))
+///     .retry_policy(RetryPolicy::default())
+///     .connect()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct KontrollBuilder {
+    port: Option<String>,
+    transport: Option<Transport>,
+    backend: Option<Arc<dyn KeyboardBackend>>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    strict: bool,
+    retry_policy: RetryPolicy,
+    listener: Option<ConnectionListener>,
+    led_layer: Option<(String, i32)>,
+}
 
-    let client = KeyboardServiceClient::new(channel);
-    Ok(client)
+impl Default for KontrollBuilder {
+    fn default() -> Self {
+        Self {
+            port: None,
+            transport: None,
+            backend: None,
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            request_timeout: None,
+            strict: false,
+            retry_policy: RetryPolicy::none(),
+            listener: None,
+            led_layer: None,
+        }
+    }
 }
 
+impl KontrollBuilder {
+    /// Sets the socket path on Unix or the port number on Windows. When not set, the
+    /// `KEYMAPP_SOCKET` or `KEYMAPP_PORT` environment variable or Keymapp's default is used.
+    /// Ignored if a transport is set.
+    pub fn port(mut self, port: impl Into<String>) -> Self {
+        self.port = Some(port.into());
+        self
+    }
 
-#[cfg(target_os = "windows")]
-pub async fn get_client(
-    port: Option<String>,
-) -> Result<KeyboardServiceClient<tonic::transport::Channel>, ApiError> {
-    // Get port number from the supplied path provided, or environment variable or set a default
-    let port = port.unwrap_or_else(|| std::env::var("KEYMAPP_PORT").unwrap_or("50051".to_string()));
-    let addr = format!("http://localhost:{}", port);
-    let timeout = std::time::Duration::from_secs(5);
+    /// Sets how Keymapp is reached, regardless of the platform Kontroll runs on. Defaults to
+    /// [`Transport::platform_default`].
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Sends calls to the given backend instead of connecting to Keymapp, the port and
+    /// transport are ignored.
+    pub fn backend(mut self, backend: impl KeyboardBackend + 'static) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Sets the time allowed to connect to Keymapp, defaults to [`DEFAULT_CONNECT_TIMEOUT`].
+    /// `None` waits forever.
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Sets the time allowed for each call to Keymapp, including brightness steps individually.
+    /// Calls have no deadline by default.
+    pub fn request_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.request_timeout = timeout.into();
+        self
+    }
+
+    /// Enables or disables strict mode. In strict mode, calls that Keymapp answers with
+    /// `success: false` return [`ApiError::Rejected`] instead of `Ok(false)`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-    match tokio::time::timeout(timeout, KeyboardServiceClient::connect(addr)).await {
-        Ok(Ok(c)) => Ok(c),
-        Err(_) => Err(ApiError { message: format!("Connection to Keymapp timed out, make sure the api is running and listening to port {}", port) }),
-        Ok(Err(e)) => Err(ApiError { message: format!("Connection to Keymapp failed, with error {}", e) })
+    /// Sets how calls failing because Keymapp can't be reached are retried. Between retries,
+    /// Kontroll reconnects to Keymapp. By default, calls are not retried. Brightness steps are
+    /// only retried when they didn't reach Keymapp, retrying them could apply a step twice.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets a listener called when the connection to Keymapp is lost and when it is restored.
+    pub fn on_connection_change<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(ConnectionEvent) + Send + Sync + 'static,
+    {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Draws the LEDs in a named layer with the given priority when connected to a Kontroll
+    /// daemon, so the LEDs set by clients with a lower priority don't cover them. Ignored by
+    /// Keymapp and when a backend is set.
+    pub fn led_layer(mut self, name: impl Into<String>, priority: i32) -> Self {
+        self.led_layer = Some((name.into(), priority));
+        self
+    }
+
+    /// Connects to Keymapp.
+    pub async fn connect(mut self) -> Result<Kontroll, ApiError> {
+        let backend: Arc<dyn KeyboardBackend> = match self.backend.take() {
+            Some(backend) => backend,
+            None => {
+                let transport = match self.transport.take() {
+                    Some(transport) => transport,
+                    None => Transport::platform_default(self.port.take())?,
+                };
+                let mut backend = GrpcBackend::connect(transport, self.connect_timeout).await?;
+                if let Some((name, priority)) = &self.led_layer {
+                    backend = backend.led_layer(name, *priority)?;
+                }
+                Arc::new(backend)
+            }
+        };
+        Ok(self.build(backend))
+    }
+
+    // Creates the instance once the backend is known.
+    fn build(self, backend: Arc<dyn KeyboardBackend>) -> Kontroll {
+        Kontroll {
+            backend,
+            connected: AtomicBool::new(true),
+            layers: Mutex::new(Vec::new()),
+            strict: self.strict,
+            request_timeout: self.request_timeout,
+            retry_policy: self.retry_policy,
+            listener: self.listener,
+        }
     }
 }
 
 impl Kontroll {
     /// Create a new Kontroll instance, connecting to Keymapp, optionally specifying a port number on Windows or a socket path on Unix.
     pub async fn new(port: Option<String>) -> Result<Self, ApiError> {
-        let client = get_client(port).await?;
-        Ok(Self { client })
+        let builder = Self::builder();
+        match port {
+            Some(port) => builder.port(port),
+            None => builder,
+        }
+        .connect()
+        .await
+    }
+
+    /// Returns a builder to configure timeouts, retries and strictness before connecting.
+    pub fn builder() -> KontrollBuilder {
+        KontrollBuilder::default()
+    }
+
+    /// Creates a Kontroll instance sending its calls to the given backend, with the default
+    /// settings.
+    pub fn with_backend(backend: impl KeyboardBackend + 'static) -> Self {
+        Self::builder().build(Arc::new(backend))
+    }
+
+    /// Enables or disables strict mode, see [`KontrollBuilder::strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets how calls failing because Keymapp can't be reached are retried, see
+    /// [`KontrollBuilder::retry_policy`].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Sets a listener called when the connection to Keymapp is lost and when it is restored,
+    /// see [`KontrollBuilder::on_connection_change`].
+    pub fn on_connection_change<F>(&mut self, listener: F)
+    where
+        F: Fn(ConnectionEvent) + Send + Sync + 'static,
+    {
+        self.listener = Some(Box::new(listener));
+    }
+
+    /// Drops the current connection and connects to Keymapp again.
+    pub async fn reconnect(&self) -> Result<(), ApiError> {
+        self.backend.reconnect().await?;
+        self.set_connected(true);
+        Ok(())
+    }
+
+    fn set_connected(&self, connected: bool) {
+        if self.connected.swap(connected, Ordering::SeqCst) != connected {
+            if let Some(listener) = &self.listener {
+                listener(if connected {
+                    ConnectionEvent::Restored
+                } else {
+                    ConnectionEvent::Lost
+                });
+            }
+        }
+    }
+
+    /// Performs a call on the backend, retrying it according to the retry policy when Keymapp
+    /// can't be reached.
+    async fn call<T, F, Fut>(&self, operation: Operation, f: F) -> Result<T, ApiError>
+    where
+        F: Fn(Arc<dyn KeyboardBackend>) -> Fut,
+        Fut: Future<Output = Result<T, tonic::Status>>,
+    {
+        let call = || {
+            let call = f(self.backend.clone());
+            with_timeout(operation, self.request_timeout, async move {
+                call.await.map_err(ApiError::rpc(operation))
+            })
+        };
+        let should_retry = |err: &ApiError| {
+            if !err.is_transport() {
+                return false;
+            }
+            self.set_connected(false);
+            operation.is_idempotent() || err.is_unsent()
+        };
+        // A failed reconnection is not fatal, the next attempt will try again.
+        let reconnect = || async {
+            let _ = self.reconnect().await;
+        };
+        self.retry_policy.run(call, should_retry, reconnect).await
+    }
+
+    fn check(&self, operation: Operation, success: bool) -> Result<bool, ApiError> {
+        if self.strict && !success {
+            return Err(ApiError::Rejected { operation });
+        }
+        Ok(success)
     }
 
     /// Gets Keymapp's version, Kontroll's version and the connected keyboard's information.
     pub async fn get_status(&self) -> Result<Status, ApiError> {
-        let req = Request::new(keymapp::GetStatusRequest {});
-        // Tonic internals require a mutable reference to the client, so we clone it here.
-        // https://github.com/hyperium/tonic/issues/33#issuecomment-538154015
-        match self.client.clone().get_status(req).await {
-            Ok(r) => {
-                let res = r.into_inner();
-                let keyboard = match res.connected_keyboard {
-                    Some(k) => Some(ConnectedKeyboard {
-                        friendly_name: k.friendly_name,
-                        firmware_version: k.firmware_version,
-                        current_layer: k.current_layer,
-                    }),
-                    None => None,
+        let res = self
+            .call(Operation::GetStatus, |backend| async move {
+                backend.get_status().await
+            })
+            .await?;
+        Ok(Status::from(res))
+    }
+
+    /// Polls Keymapp's status every `interval`, yielding what changed between two polls. The
+    /// keyboard connected when watching starts is reported as connected.
+    ///
+    /// An `interval` shorter than a millisecond polls every millisecond. Failed polls are
+    /// skipped, watching goes on until the stream is dropped. Use
+    /// [`KontrollBuilder::on_connection_change`] to know when Keymapp can't be reached.
+    ///
+    /// ```rust,no_run
+    /// # async fn run(api: kontroll::Kontroll) {
+    /// use std::time::Duration;
+    /// use tokio_stream::StreamExt;
+    ///
+    /// let events = api.watch_status(Duration::from_millis(500));
+    /// tokio::pin!(events);
+    /// while let Some(event) = events.next().await {
+    ///     println!("{}", event);
+    /// }
+    /// # }
+    /// ```
+    pub fn watch_status(&self, interval: Duration) -> impl Stream<Item = StatusEvent> + '_ {
+        async_stream::stream! {
+            let mut ticks = tokio::time::interval(interval.max(MIN_WATCH_INTERVAL));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut previous = None;
+            loop {
+                ticks.tick().await;
+                let Ok(status) = self.get_status().await else {
+                    continue;
                 };
-                Ok(Status {
-                    keymapp_version: res.keymapp_version,
-                    kontroll_version: env!("CARGO_PKG_VERSION").to_string(),
-                    keyboard,
-                })
+                for event in watch::events(previous.as_ref(), &status) {
+                    yield event;
+                }
+                previous = Some(status);
             }
-            Err(e) => Err(ApiError {
-                message: format!("Failed to get status: {}", e.message()),
-            }),
         }
     }
 
     /// Gets a list of available keyboards.
     pub async fn list_keyboards(&self) -> Result<Vec<Keyboard>, ApiError> {
-        let req = Request::new(GetKeyboardsRequest {});
-        let res = match self.client.clone().get_keyboards(req).await {
-            Ok(r) => r.into_inner().keyboards,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to get keyboards: {}", e.message()),
-                })
-            }
-        };
-        Ok(res)
+        let res = self
+            .call(Operation::GetKeyboards, |backend| async move {
+                backend.get_keyboards().await
+            })
+            .await?;
+        Ok(res.keyboards)
     }
 
     /// Connects to a keyboard by index.
     pub async fn connect(&self, index: usize) -> Result<bool, ApiError> {
-        let req = Request::new(ConnectKeyboardRequest { id: index as i32 });
-        let res = match self.client.clone().connect_keyboard(req).await {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to connect: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+        let operation = Operation::Connect { index };
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .connect_keyboard(ConnectKeyboardRequest { id: index as i32 })
+                    .await
+            })
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Connects to the first entry in the list of available keyboards.
     pub async fn connect_any(&self) -> Result<bool, ApiError> {
-        let req = Request::new(ConnectAnyKeyboardRequest {});
-        let res = match self.client.clone().connect_any_keyboard(req).await {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to connect: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+        let operation = Operation::ConnectAny;
+        let res = self
+            .call(operation, |backend| async move {
+                backend.connect_any_keyboard().await
+            })
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Sets a layer by index on the connected keyboard.
     pub async fn set_layer(&self, index: usize) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .set_layer(SetLayerRequest {
-                layer: index as i32,
+        let operation = Operation::SetLayer { index };
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .set_layer(SetLayerRequest {
+                        layer: index as i32,
+                    })
+                    .await
             })
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to set layer: {}", e.message()),
-                })
+            .await?;
+        self.check(operation, res.success)
+    }
+
+    /// Unsets a previously set layer on the connected keyboard. If the layer was pushed with
+    /// [`Kontroll::push_layer`], it is also removed from the layer stack.
+    pub async fn unset_layer(&self, index: usize) -> Result<bool, ApiError> {
+        let operation = Operation::UnsetLayer { index };
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .unset_layer(SetLayerRequest {
+                        layer: index as i32,
+                    })
+                    .await
+            })
+            .await?
+            .success;
+
+        if res {
+            let mut layers = self.layers.lock().unwrap();
+            if let Some(pos) = layers.iter().rposition(|l| *l == index) {
+                layers.remove(pos);
             }
-        };
+        }
 
+        self.check(operation, res)
+    }
+
+    /// Sets a layer and pushes it on top of the layer stack, so it can be unwound later with
+    /// [`Kontroll::pop_layer`].
+    pub async fn push_layer(&self, index: usize) -> Result<bool, ApiError> {
+        let res = self.set_layer(index).await?;
+        if res {
+            self.layers.lock().unwrap().push(index);
+        }
         Ok(res)
     }
 
+    /// Unsets the layer on top of the layer stack, returning its index. Returns `None` if no
+    /// layer was pushed.
+    pub async fn pop_layer(&self) -> Result<Option<usize>, ApiError> {
+        let index = match self.layers.lock().unwrap().last() {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+        // The layer stays on the stack if Keymapp refuses to unset it, so unwinding must fail
+        // here even outside of strict mode.
+        if !self.unset_layer(index).await? {
+            return Err(ApiError::Rejected {
+                operation: Operation::UnsetLayer { index },
+            });
+        }
+        Ok(Some(index))
+    }
+
+    /// Unsets every layer on the layer stack, most recently pushed first.
+    pub async fn unwind_layers(&self) -> Result<Vec<usize>, ApiError> {
+        let mut popped = Vec::new();
+        while let Some(index) = self.pop_layer().await? {
+            popped.push(index);
+        }
+        Ok(popped)
+    }
+
+    /// Returns the layers pushed with [`Kontroll::push_layer`] that are still set, bottom first.
+    pub fn layer_stack(&self) -> Vec<usize> {
+        self.layers.lock().unwrap().clone()
+    }
+
     /// Sets an RGB LED by index on the connected keyboard.
     pub async fn set_rgb_led(
         &self,
@@ -254,75 +783,56 @@ impl Kontroll {
         b: u8,
         sustain: i32,
     ) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .set_rgb_led(SetRgbLedRequest {
-                led: index as i32,
-                red: r as i32,
-                green: g as i32,
-                blue: b as i32,
-                sustain,
-            })
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to set rgb: {}", e.message()),
-                })
-            }
+        let operation = Operation::SetRgbLed {
+            led: index,
+            r,
+            g,
+            b,
+            sustain,
         };
-
-        Ok(res)
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .set_rgb_led(SetRgbLedRequest {
+                        led: index as i32,
+                        red: r as i32,
+                        green: g as i32,
+                        blue: b as i32,
+                        sustain,
+                    })
+                    .await
+            })
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Sets all RGB LEDs on the connected keyboard.
     pub async fn set_rgb_all(&self, r: u8, g: u8, b: u8, sustain: i32) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .set_rgb_all(SetRgbAllRequest {
-                red: r as i32,
-                green: g as i32,
-                blue: b as i32,
-                sustain,
+        let operation = Operation::SetRgbAll { r, g, b, sustain };
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .set_rgb_all(SetRgbAllRequest {
+                        red: r as i32,
+                        green: g as i32,
+                        blue: b as i32,
+                        sustain,
+                    })
+                    .await
             })
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to set rgb: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Restores all RGB LEDs on the connected keyboard.
     pub async fn restore_rgb_leds(&self) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .set_rgb_all(SetRgbAllRequest {
-                red: 0,
-                green: 0,
-                blue: 0,
-                sustain: 1,
+        let operation = Operation::RestoreRgbLeds;
+        let res = self
+            .call(operation, |backend| async move {
+                backend.restore_rgb_leds().await
             })
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to set rgb: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Sets a status LED by index on the connected keyboard.
@@ -332,116 +842,77 @@ impl Kontroll {
         on: bool,
         sustain: i32,
     ) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .set_status_led(keymapp::SetStatusLedRequest {
-                led: led as i32,
-                on,
-                sustain,
+        let operation = Operation::SetStatusLed { led, on, sustain };
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .set_status_led(SetStatusLedRequest {
+                        led: led as i32,
+                        on,
+                        sustain,
+                    })
+                    .await
             })
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to set status led: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Restores all status LEDs on the connected keyboard.
     pub async fn restore_status_leds(&self) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .set_status_led(keymapp::SetStatusLedRequest {
-                led: 0,
-                on: false,
-                sustain: 1,
+        let operation = Operation::RestoreStatusLeds;
+        let res = self
+            .call(operation, |backend| async move {
+                backend
+                    .set_status_led(SetStatusLedRequest {
+                        led: 0,
+                        on: false,
+                        sustain: 1,
+                    })
+                    .await
             })
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to set status led: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+            .await?;
+        self.check(operation, res.success)
     }
 
     /// Sets the brightness of the connected keyboard. Several steps can be taken.
     pub async fn update_brightness(&self, increase: bool, steps: i32) -> Result<bool, ApiError> {
         let mut res = false;
         if !(1..=255).contains(&steps) {
-            return Err(ApiError {
-                message: "Brightness steps must be between 1 and 255".to_string(),
-            });
+            return Err(ApiError::InvalidArgument(
+                "Brightness steps must be between 1 and 255".to_string(),
+            ));
         }
-        if increase {
-            for _ in 0..steps {
-                res = match self
-                    .client
-                    .clone()
-                    .increase_brightness(keymapp::IncreaseBrightnessRequest {})
-                    .await
-                {
-                    Ok(r) => r.into_inner().success,
-                    Err(e) => {
-                        return Err(ApiError {
-                            message: format!("Failed to increase brightness: {}", e.message()),
-                        })
-                    }
-                };
-                if !res {
-                    break;
-                }
-            }
+        let operation = if increase {
+            Operation::IncreaseBrightness
         } else {
-            for _ in 0..steps {
-                res = match self
-                    .client
-                    .clone()
-                    .decrease_brightness(keymapp::DecreaseBrightnessRequest {})
-                    .await
-                {
-                    Ok(r) => r.into_inner().success,
-                    Err(e) => {
-                        return Err(ApiError {
-                            message: format!("Failed to decrease brightness: {}", e.message()),
-                        })
+            Operation::DecreaseBrightness
+        };
+        for _ in 0..steps {
+            res = self
+                .call(operation, |backend| async move {
+                    if increase {
+                        backend.increase_brightness().await
+                    } else {
+                        backend.decrease_brightness().await
                     }
-                };
-                if !res {
-                    break;
-                }
+                })
+                .await?
+                .success;
+            if !res {
+                break;
             }
         }
-        Ok(res)
+        self.check(operation, res)
     }
 
     /// Disconnects the connected keyboard.
     pub async fn disconnect(&self) -> Result<bool, ApiError> {
-        let res = match self
-            .client
-            .clone()
-            .disconnect_keyboard(DisconnectKeyboardRequest {})
-            .await
-        {
-            Ok(r) => r.into_inner().success,
-            Err(e) => {
-                return Err(ApiError {
-                    message: format!("Failed to disconnect: {}", e.message()),
-                })
-            }
-        };
-
-        Ok(res)
+        let operation = Operation::Disconnect;
+        let res = self
+            .call(operation, |backend| async move {
+                backend.disconnect_keyboard().await
+            })
+            .await?;
+        self.check(operation, res.success)
     }
 }
@@ -0,0 +1,727 @@
+//! The backends [`Kontroll`](crate::Kontroll) sends its calls to.
+//!
+//! [`GrpcBackend`] talks to Keymapp and is used by default. [`RecordingBackend`] is an
+//! in-process fake recording every call, so applications can be tested without Keymapp:
+//!
+//! ```rust
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use kontroll::backend::RecordingBackend;
+//! use kontroll::Kontroll;
+//!
+//! let backend = RecordingBackend::new();
+//! let api = Kontroll::with_backend(backend.clone());
+//! api.set_rgb_led(12, 255, 0, 0, 0).await.unwrap();
+//! assert_eq!(backend.rgb_led(12), Some((255, 0, 0)));
+//! # }
+//! ```
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::transport::Channel;
+use tonic::{Request, Response, Status};
+
+use crate::api::keymapp::{
+    keyboard_service_client::KeyboardServiceClient,
+    keyboard_service_server::{KeyboardService, KeyboardServiceServer},
+    BrightnessUpdateReply, ConnectAnyKeyboardRequest, ConnectKeyboardReply, ConnectKeyboardRequest,
+    DecreaseBrightnessRequest, DisconnectKeyboardReply, DisconnectKeyboardRequest,
+    GetKeyboardsReply, GetKeyboardsRequest, GetStatusReply, GetStatusRequest,
+    IncreaseBrightnessRequest, Keyboard, SetLayerReply, SetLayerRequest, SetRgbAllReply,
+    SetRgbAllRequest, SetRgbLedReply, SetRgbLedRequest, SetStatusLedReply, SetStatusLedRequest,
+};
+use crate::api::{connect_client, ApiError};
+use crate::compositor::{LAYER_HEADER, PRIORITY_HEADER, RESTORE_HEADER};
+use crate::transport::Transport;
+
+/// The calls Kontroll makes to a keyboard, mirroring Keymapp's `KeyboardService`.
+#[tonic::async_trait]
+pub trait KeyboardBackend: Send + Sync {
+    async fn get_status(&self) -> Result<GetStatusReply, Status>;
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status>;
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status>;
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status>;
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status>;
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status>;
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status>;
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status>;
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status>;
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status>;
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status>;
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status>;
+
+    /// Restores all RGB LEDs to their default color. Keymapp has no call for it, setting all
+    /// LEDs to black for 1ms lets them fall back to their default color.
+    async fn restore_rgb_leds(&self) -> Result<SetRgbAllReply, Status> {
+        self.set_rgb_all(RESTORE_RGB_LEDS).await
+    }
+
+    /// Called before retrying a call that failed because the backend couldn't be reached.
+    async fn reconnect(&self) -> Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+// Lets a backend be shared, for example to inspect it while Kontroll or a server uses it.
+#[tonic::async_trait]
+impl<B: KeyboardBackend + ?Sized> KeyboardBackend for Arc<B> {
+    async fn get_status(&self) -> Result<GetStatusReply, Status> {
+        (**self).get_status().await
+    }
+
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status> {
+        (**self).get_keyboards().await
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status> {
+        (**self).connect_keyboard(request).await
+    }
+
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status> {
+        (**self).connect_any_keyboard().await
+    }
+
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status> {
+        (**self).disconnect_keyboard().await
+    }
+
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        (**self).set_layer(request).await
+    }
+
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        (**self).unset_layer(request).await
+    }
+
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status> {
+        (**self).set_rgb_led(request).await
+    }
+
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status> {
+        (**self).set_rgb_all(request).await
+    }
+
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status> {
+        (**self).set_status_led(request).await
+    }
+
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        (**self).increase_brightness().await
+    }
+
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        (**self).decrease_brightness().await
+    }
+
+    async fn restore_rgb_leds(&self) -> Result<SetRgbAllReply, Status> {
+        (**self).restore_rgb_leds().await
+    }
+
+    async fn reconnect(&self) -> Result<(), ApiError> {
+        (**self).reconnect().await
+    }
+}
+
+/// The call [`KeyboardBackend::restore_rgb_leds`] makes to Keymapp.
+pub(crate) const RESTORE_RGB_LEDS: SetRgbAllRequest = SetRgbAllRequest {
+    red: 0,
+    green: 0,
+    blue: 0,
+    sustain: 1,
+};
+
+/// The backend talking to Keymapp over gRPC.
+pub struct GrpcBackend {
+    // Kept to reconnect to the same endpoint when the connection is lost.
+    transport: Transport,
+    connect_timeout: Option<Duration>,
+    client: RwLock<KeyboardServiceClient<Channel>>,
+    metadata: MetadataMap,
+}
+
+impl GrpcBackend {
+    /// Connects to Keymapp over the given transport.
+    pub async fn connect(
+        transport: Transport,
+        connect_timeout: Option<Duration>,
+    ) -> Result<Self, ApiError> {
+        let client = connect_client(&transport, connect_timeout).await?;
+        Ok(Self {
+            transport,
+            connect_timeout,
+            client: RwLock::new(client),
+            metadata: MetadataMap::new(),
+        })
+    }
+
+    /// Draws the LEDs in the given layer of the daemon's compositor, see
+    /// [`compositor`](crate::compositor). Keymapp itself ignores the layer.
+    pub fn led_layer(mut self, name: &str, priority: i32) -> Result<Self, ApiError> {
+        let name = MetadataValue::try_from(name).map_err(|_| {
+            ApiError::InvalidArgument(format!("{} is not a valid LED layer name", name))
+        })?;
+        self.metadata.insert(LAYER_HEADER, name);
+        self.metadata.insert(PRIORITY_HEADER, priority.into());
+        Ok(self)
+    }
+
+    // Tonic internals require a mutable reference to the client, so we clone it here.
+    // https://github.com/hyperium/tonic/issues/33#issuecomment-538154015
+    fn client(&self) -> KeyboardServiceClient<Channel> {
+        self.client.read().unwrap().clone()
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        *request.metadata_mut() = self.metadata.clone();
+        request
+    }
+}
+
+#[tonic::async_trait]
+impl KeyboardBackend for GrpcBackend {
+    async fn get_status(&self) -> Result<GetStatusReply, Status> {
+        let res = self
+            .client()
+            .get_status(self.request(GetStatusRequest {}))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status> {
+        let res = self
+            .client()
+            .get_keyboards(self.request(GetKeyboardsRequest {}))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status> {
+        let res = self
+            .client()
+            .connect_keyboard(self.request(request))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status> {
+        let res = self
+            .client()
+            .connect_any_keyboard(self.request(ConnectAnyKeyboardRequest {}))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status> {
+        let res = self
+            .client()
+            .disconnect_keyboard(self.request(DisconnectKeyboardRequest {}))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let res = self.client().set_layer(self.request(request)).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let res = self.client().unset_layer(self.request(request)).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status> {
+        let res = self.client().set_rgb_led(self.request(request)).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status> {
+        let res = self.client().set_rgb_all(self.request(request)).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status> {
+        let res = self.client().set_status_led(self.request(request)).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let res = self
+            .client()
+            .increase_brightness(self.request(IncreaseBrightnessRequest {}))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let res = self
+            .client()
+            .decrease_brightness(self.request(DecreaseBrightnessRequest {}))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    // Keymapp restores the LEDs from the request, the daemon from the header.
+    async fn restore_rgb_leds(&self) -> Result<SetRgbAllReply, Status> {
+        let mut request = self.request(RESTORE_RGB_LEDS);
+        request
+            .metadata_mut()
+            .insert(RESTORE_HEADER, MetadataValue::from_static("1"));
+        let res = self.client().set_rgb_all(request).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn reconnect(&self) -> Result<(), ApiError> {
+        let client = connect_client(&self.transport, self.connect_timeout).await?;
+        *self.client.write().unwrap() = client;
+        Ok(())
+    }
+}
+
+/// Serves a backend as Keymapp's `KeyboardService`, so clients can connect to it like they
+/// would to Keymapp.
+pub struct BackendService<B> {
+    backend: B,
+}
+
+impl<B: KeyboardBackend + 'static> BackendService<B> {
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    /// Wraps the service in the generated server, ready to be added to a tonic router.
+    pub fn into_server(self) -> KeyboardServiceServer<Self> {
+        KeyboardServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl<B: KeyboardBackend + 'static> KeyboardService for BackendService<B> {
+    async fn get_status(
+        &self,
+        _request: Request<GetStatusRequest>,
+    ) -> Result<Response<GetStatusReply>, Status> {
+        self.backend.get_status().await.map(Response::new)
+    }
+
+    async fn get_keyboards(
+        &self,
+        _request: Request<GetKeyboardsRequest>,
+    ) -> Result<Response<GetKeyboardsReply>, Status> {
+        self.backend.get_keyboards().await.map(Response::new)
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: Request<ConnectKeyboardRequest>,
+    ) -> Result<Response<ConnectKeyboardReply>, Status> {
+        let request = request.into_inner();
+        self.backend
+            .connect_keyboard(request)
+            .await
+            .map(Response::new)
+    }
+
+    async fn connect_any_keyboard(
+        &self,
+        _request: Request<ConnectAnyKeyboardRequest>,
+    ) -> Result<Response<ConnectKeyboardReply>, Status> {
+        self.backend.connect_any_keyboard().await.map(Response::new)
+    }
+
+    async fn disconnect_keyboard(
+        &self,
+        _request: Request<DisconnectKeyboardRequest>,
+    ) -> Result<Response<DisconnectKeyboardReply>, Status> {
+        self.backend.disconnect_keyboard().await.map(Response::new)
+    }
+
+    async fn set_layer(
+        &self,
+        request: Request<SetLayerRequest>,
+    ) -> Result<Response<SetLayerReply>, Status> {
+        let request = request.into_inner();
+        self.backend.set_layer(request).await.map(Response::new)
+    }
+
+    async fn unset_layer(
+        &self,
+        request: Request<SetLayerRequest>,
+    ) -> Result<Response<SetLayerReply>, Status> {
+        let request = request.into_inner();
+        self.backend.unset_layer(request).await.map(Response::new)
+    }
+
+    async fn set_rgb_led(
+        &self,
+        request: Request<SetRgbLedRequest>,
+    ) -> Result<Response<SetRgbLedReply>, Status> {
+        let request = request.into_inner();
+        self.backend.set_rgb_led(request).await.map(Response::new)
+    }
+
+    async fn set_rgb_all(
+        &self,
+        request: Request<SetRgbAllRequest>,
+    ) -> Result<Response<SetRgbAllReply>, Status> {
+        if request.metadata().contains_key(RESTORE_HEADER) {
+            return self.backend.restore_rgb_leds().await.map(Response::new);
+        }
+        let request = request.into_inner();
+        self.backend.set_rgb_all(request).await.map(Response::new)
+    }
+
+    async fn set_status_led(
+        &self,
+        request: Request<SetStatusLedRequest>,
+    ) -> Result<Response<SetStatusLedReply>, Status> {
+        let request = request.into_inner();
+        self.backend
+            .set_status_led(request)
+            .await
+            .map(Response::new)
+    }
+
+    async fn increase_brightness(
+        &self,
+        _request: Request<IncreaseBrightnessRequest>,
+    ) -> Result<Response<BrightnessUpdateReply>, Status> {
+        self.backend.increase_brightness().await.map(Response::new)
+    }
+
+    async fn decrease_brightness(
+        &self,
+        _request: Request<DecreaseBrightnessRequest>,
+    ) -> Result<Response<BrightnessUpdateReply>, Status> {
+        self.backend.decrease_brightness().await.map(Response::new)
+    }
+}
+
+/// A call received by a [`RecordingBackend`], or recorded in a session by the proxy.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "method")
+)]
+pub enum Call {
+    GetStatus,
+    GetKeyboards,
+    ConnectKeyboard(ConnectKeyboardRequest),
+    ConnectAnyKeyboard,
+    DisconnectKeyboard,
+    SetLayer(SetLayerRequest),
+    UnsetLayer(SetLayerRequest),
+    SetRgbLed(SetRgbLedRequest),
+    SetRgbAll(SetRgbAllRequest),
+    SetStatusLed(SetStatusLedRequest),
+    IncreaseBrightness,
+    DecreaseBrightness,
+}
+
+struct Recording {
+    calls: Vec<Call>,
+    status: GetStatusReply,
+    keyboards: Vec<Keyboard>,
+    success: bool,
+    next_error: Option<Status>,
+}
+
+/// An in-process fake recording every call it receives.
+///
+/// Clones share the same recording, so a clone can be handed to
+/// [`Kontroll::with_backend`](crate::Kontroll::with_backend) and the original used to
+/// inspect the calls.
+#[derive(Clone)]
+pub struct RecordingBackend {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Default for RecordingBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordingBackend {
+    /// Creates a backend answering every call successfully, with no keyboard connected.
+    pub fn new() -> Self {
+        Self {
+            recording: Arc::new(Mutex::new(Recording {
+                calls: Vec::new(),
+                status: GetStatusReply {
+                    keymapp_version: "recording".to_string(),
+                    connected_keyboard: None,
+                },
+                keyboards: Vec::new(),
+                success: true,
+                next_error: None,
+            })),
+        }
+    }
+
+    /// Returns the calls received so far, oldest first.
+    pub fn calls(&self) -> Vec<Call> {
+        self.recording.lock().unwrap().calls.clone()
+    }
+
+    /// Forgets the calls received so far.
+    pub fn clear_calls(&self) {
+        self.recording.lock().unwrap().calls.clear();
+    }
+
+    /// Sets the reply to `GetStatus` calls.
+    pub fn set_status(&self, status: GetStatusReply) {
+        self.recording.lock().unwrap().status = status;
+    }
+
+    /// Sets the keyboards returned by `GetKeyboards` calls.
+    pub fn set_keyboards(&self, keyboards: Vec<Keyboard>) {
+        self.recording.lock().unwrap().keyboards = keyboards;
+    }
+
+    /// Sets the `success` field of replies, `false` simulates Keymapp refusing the calls.
+    pub fn set_success(&self, success: bool) {
+        self.recording.lock().unwrap().success = success;
+    }
+
+    /// Makes the next call fail with the given status. The failed call is still recorded.
+    pub fn fail_next(&self, status: Status) {
+        self.recording.lock().unwrap().next_error = Some(status);
+    }
+
+    /// Returns the last color set on an RGB LED, either directly or by setting all LEDs.
+    pub fn rgb_led(&self, led: usize) -> Option<(u8, u8, u8)> {
+        let recording = self.recording.lock().unwrap();
+        recording.calls.iter().rev().find_map(|call| match call {
+            Call::SetRgbLed(r) if r.led as usize == led => {
+                Some((r.red as u8, r.green as u8, r.blue as u8))
+            }
+            Call::SetRgbAll(r) => Some((r.red as u8, r.green as u8, r.blue as u8)),
+            _ => None,
+        })
+    }
+
+    /// Returns the last state set on a status LED.
+    pub fn status_led(&self, led: usize) -> Option<bool> {
+        let recording = self.recording.lock().unwrap();
+        recording.calls.iter().rev().find_map(|call| match call {
+            Call::SetStatusLed(r) if r.led as usize == led => Some(r.on),
+            _ => None,
+        })
+    }
+
+    // Returns the same error type as the backend calls it is used by.
+    #[allow(clippy::result_large_err)]
+    fn record(&self, call: Call) -> Result<bool, Status> {
+        let mut recording = self.recording.lock().unwrap();
+        recording.calls.push(call);
+        match recording.next_error.take() {
+            Some(status) => Err(status),
+            None => Ok(recording.success),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl KeyboardBackend for RecordingBackend {
+    async fn get_status(&self) -> Result<GetStatusReply, Status> {
+        self.record(Call::GetStatus)?;
+        Ok(self.recording.lock().unwrap().status.clone())
+    }
+
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status> {
+        self.record(Call::GetKeyboards)?;
+        Ok(GetKeyboardsReply {
+            keyboards: self.recording.lock().unwrap().keyboards.clone(),
+        })
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status> {
+        let success = self.record(Call::ConnectKeyboard(request))?;
+        Ok(ConnectKeyboardReply { success })
+    }
+
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status> {
+        let success = self.record(Call::ConnectAnyKeyboard)?;
+        Ok(ConnectKeyboardReply { success })
+    }
+
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status> {
+        let success = self.record(Call::DisconnectKeyboard)?;
+        Ok(DisconnectKeyboardReply { success })
+    }
+
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let success = self.record(Call::SetLayer(request))?;
+        Ok(SetLayerReply { success })
+    }
+
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let success = self.record(Call::UnsetLayer(request))?;
+        Ok(SetLayerReply { success })
+    }
+
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status> {
+        let success = self.record(Call::SetRgbLed(request))?;
+        Ok(SetRgbLedReply { success })
+    }
+
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status> {
+        let success = self.record(Call::SetRgbAll(request))?;
+        Ok(SetRgbAllReply { success })
+    }
+
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status> {
+        let success = self.record(Call::SetStatusLed(request))?;
+        Ok(SetStatusLedReply { success })
+    }
+
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let success = self.record(Call::IncreaseBrightness)?;
+        Ok(BrightnessUpdateReply { success })
+    }
+
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let success = self.record(Call::DecreaseBrightness)?;
+        Ok(BrightnessUpdateReply { success })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Kontroll;
+
+    #[tokio::test]
+    async fn records_calls() {
+        let backend = RecordingBackend::new();
+        let api = Kontroll::with_backend(backend.clone());
+
+        api.push_layer(2).await.unwrap();
+        api.set_rgb_all(0, 0, 255, 0).await.unwrap();
+        api.set_rgb_led(12, 255, 0, 0, 0).await.unwrap();
+        api.set_status_led(1, true, 0).await.unwrap();
+        api.pop_layer().await.unwrap();
+
+        assert_eq!(backend.rgb_led(12), Some((255, 0, 0)));
+        assert_eq!(backend.rgb_led(3), Some((0, 0, 255)));
+        assert_eq!(backend.status_led(1), Some(true));
+        assert_eq!(
+            backend.calls()[0],
+            Call::SetLayer(SetLayerRequest { layer: 2 })
+        );
+        assert_eq!(
+            backend.calls().last(),
+            Some(&Call::UnsetLayer(SetLayerRequest { layer: 2 }))
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_calls_fail_in_strict_mode() {
+        let backend = RecordingBackend::new();
+        backend.set_success(false);
+        let mut api = Kontroll::builder()
+            .backend(backend.clone())
+            .strict(true)
+            .connect()
+            .await
+            .unwrap();
+
+        let err = api.set_layer(7).await.unwrap_err();
+        assert!(matches!(err, ApiError::Rejected { .. }));
+        assert!(!err.is_transport());
+
+        api.set_strict(false);
+        assert!(!api.set_layer(7).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_retried() {
+        let backend = RecordingBackend::new();
+        backend.fail_next(Status::unavailable("keymapp restarting"));
+        let api = Kontroll::builder()
+            .backend(backend.clone())
+            .retry_policy(crate::RetryPolicy {
+                initial_backoff: Duration::ZERO,
+                ..crate::RetryPolicy::with_max_retries(1)
+            })
+            .connect()
+            .await
+            .unwrap();
+
+        assert!(api.set_layer(1).await.unwrap());
+        assert_eq!(backend.calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_retried() {
+        let backend = RecordingBackend::new();
+        backend.fail_next(Status::unknown("app error"));
+        let api = Kontroll::builder()
+            .backend(backend.clone())
+            .retry_policy(crate::RetryPolicy {
+                initial_backoff: Duration::ZERO,
+                ..crate::RetryPolicy::with_max_retries(1)
+            })
+            .connect()
+            .await
+            .unwrap();
+
+        assert!(!api.set_layer(1).await.unwrap_err().is_transport());
+        assert_eq!(backend.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sent_brightness_changes_are_not_retried() {
+        let backend = RecordingBackend::new();
+        let api = Kontroll::builder()
+            .backend(backend.clone())
+            .retry_policy(crate::RetryPolicy {
+                initial_backoff: Duration::ZERO,
+                ..crate::RetryPolicy::with_max_retries(1)
+            })
+            .connect()
+            .await
+            .unwrap();
+
+        // Keymapp may have applied a call whose deadline passed.
+        backend.fail_next(Status::deadline_exceeded("no reply"));
+        assert!(api
+            .update_brightness(true, 1)
+            .await
+            .unwrap_err()
+            .is_transport());
+        assert_eq!(backend.calls(), vec![Call::IncreaseBrightness]);
+
+        backend.clear_calls();
+        backend.fail_next(Status::unavailable("keymapp restarting"));
+        assert!(api.update_brightness(false, 1).await.unwrap());
+        assert_eq!(backend.calls().len(), 2);
+    }
+}
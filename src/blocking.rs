@@ -0,0 +1,195 @@
+//! A blocking Kontroll client, for programs that don't run an async runtime such as game loops,
+//! GUIs or simple tools.
+//!
+//! ```rust,no_run
+//! use kontroll::blocking::Kontroll;
+//!
+//! let api = Kontroll::new(None).unwrap();
+//! api.set_rgb_all(255, 0, 0, 0).unwrap();
+//! ```
+//!
+//! The client owns a single-threaded tokio runtime and blocks on each call. It must not be used
+//! from within an async runtime, use [`crate::Kontroll`] there instead.
+use std::future::poll_fn;
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::runtime::Runtime;
+
+use crate::api::keymapp::Keyboard;
+use crate::api::{ApiError, KontrollBuilder, Status};
+use crate::retry::{ConnectionEvent, RetryPolicy};
+use crate::watch::StatusEvent;
+
+/// The blocking counterpart of [`crate::Kontroll`], see the async client for details on
+/// each method.
+pub struct Kontroll {
+    inner: crate::Kontroll,
+    runtime: Runtime,
+}
+
+impl Kontroll {
+    /// Create a new Kontroll instance, connecting to Keymapp, optionally specifying a port number on Windows or a socket path on Unix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime can't be created, or if called from within an async runtime.
+    pub fn new(port: Option<String>) -> Result<Self, ApiError> {
+        let builder = crate::Kontroll::builder();
+        Self::from_builder(match port {
+            Some(port) => builder.port(port),
+            None => builder,
+        })
+    }
+
+    /// Returns a builder to configure timeouts, retries and strictness, to connect with
+    /// [`Kontroll::from_builder`].
+    pub fn builder() -> KontrollBuilder {
+        crate::Kontroll::builder()
+    }
+
+    /// Connects to Keymapp with a configured [`KontrollBuilder`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime can't be created, or if called from within an async runtime.
+    pub fn from_builder(builder: KontrollBuilder) -> Result<Self, ApiError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Unable to create a runtime");
+        let inner = runtime.block_on(builder.connect())?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// Enables or disables strict mode, see [`KontrollBuilder::strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.inner.set_strict(strict);
+    }
+
+    /// Sets how calls failing because Keymapp can't be reached are retried, see
+    /// [`KontrollBuilder::retry_policy`].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.inner.set_retry_policy(policy);
+    }
+
+    /// Sets a listener called when the connection to Keymapp is lost and when it is restored,
+    /// see [`KontrollBuilder::on_connection_change`].
+    pub fn on_connection_change<F>(&mut self, listener: F)
+    where
+        F: Fn(ConnectionEvent) + Send + Sync + 'static,
+    {
+        self.inner.on_connection_change(listener);
+    }
+
+    /// Drops the current connection and connects to Keymapp again.
+    pub fn reconnect(&self) -> Result<(), ApiError> {
+        self.runtime.block_on(self.inner.reconnect())
+    }
+
+    /// Gets Keymapp's version, Kontroll's version and the connected keyboard's information.
+    pub fn get_status(&self) -> Result<Status, ApiError> {
+        self.runtime.block_on(self.inner.get_status())
+    }
+
+    /// Polls the status every `interval` and yields its changes, blocking until the next one.
+    /// The iterator never ends.
+    pub fn watch_status(&self, interval: Duration) -> impl Iterator<Item = StatusEvent> + '_ {
+        let mut events = Box::pin(self.inner.watch_status(interval));
+        std::iter::from_fn(move || {
+            self.runtime
+                .block_on(poll_fn(|cx| events.as_mut().poll_next(cx)))
+        })
+    }
+
+    /// Gets a list of available keyboards.
+    pub fn list_keyboards(&self) -> Result<Vec<Keyboard>, ApiError> {
+        self.runtime.block_on(self.inner.list_keyboards())
+    }
+
+    /// Connects to a keyboard by index.
+    pub fn connect(&self, index: usize) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.connect(index))
+    }
+
+    /// Connects to the first entry in the list of available keyboards.
+    pub fn connect_any(&self) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.connect_any())
+    }
+
+    /// Sets a layer by index on the connected keyboard.
+    pub fn set_layer(&self, index: usize) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.set_layer(index))
+    }
+
+    /// Unsets a previously set layer on the connected keyboard.
+    pub fn unset_layer(&self, index: usize) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.unset_layer(index))
+    }
+
+    /// Sets a layer and pushes it on top of the layer stack.
+    pub fn push_layer(&self, index: usize) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.push_layer(index))
+    }
+
+    /// Unsets the layer on top of the layer stack, returning its index.
+    pub fn pop_layer(&self) -> Result<Option<usize>, ApiError> {
+        self.runtime.block_on(self.inner.pop_layer())
+    }
+
+    /// Unsets every layer on the layer stack, most recently pushed first.
+    pub fn unwind_layers(&self) -> Result<Vec<usize>, ApiError> {
+        self.runtime.block_on(self.inner.unwind_layers())
+    }
+
+    /// Returns the layers pushed with [`Kontroll::push_layer`] that are still set, bottom first.
+    pub fn layer_stack(&self) -> Vec<usize> {
+        self.inner.layer_stack()
+    }
+
+    /// Sets an RGB LED by index on the connected keyboard.
+    pub fn set_rgb_led(
+        &self,
+        index: usize,
+        r: u8,
+        g: u8,
+        b: u8,
+        sustain: i32,
+    ) -> Result<bool, ApiError> {
+        self.runtime
+            .block_on(self.inner.set_rgb_led(index, r, g, b, sustain))
+    }
+
+    /// Sets all RGB LEDs on the connected keyboard.
+    pub fn set_rgb_all(&self, r: u8, g: u8, b: u8, sustain: i32) -> Result<bool, ApiError> {
+        self.runtime
+            .block_on(self.inner.set_rgb_all(r, g, b, sustain))
+    }
+
+    /// Restores all RGB LEDs on the connected keyboard.
+    pub fn restore_rgb_leds(&self) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.restore_rgb_leds())
+    }
+
+    /// Sets a status LED by index on the connected keyboard.
+    pub fn set_status_led(&self, led: usize, on: bool, sustain: i32) -> Result<bool, ApiError> {
+        self.runtime
+            .block_on(self.inner.set_status_led(led, on, sustain))
+    }
+
+    /// Restores all status LEDs on the connected keyboard.
+    pub fn restore_status_leds(&self) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.restore_status_leds())
+    }
+
+    /// Sets the brightness of the connected keyboard. Several steps can be taken.
+    pub fn update_brightness(&self, increase: bool, steps: i32) -> Result<bool, ApiError> {
+        self.runtime
+            .block_on(self.inner.update_brightness(increase, steps))
+    }
+
+    /// Disconnects the connected keyboard.
+    pub fn disconnect(&self) -> Result<bool, ApiError> {
+        self.runtime.block_on(self.inner.disconnect())
+    }
+}
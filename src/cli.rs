@@ -1,7 +1,38 @@
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{Args, Parser, Subcommand};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+#[cfg(unix)]
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio_stream::StreamExt;
 
-use kontroll::{utils, Kontroll};
+#[cfg(unix)]
+use kontroll::api::DEFAULT_CONNECT_TIMEOUT;
+#[cfg(unix)]
+use kontroll::backend::GrpcBackend;
+use kontroll::color::{Color, Gradient, Interpolation};
+#[cfg(unix)]
+use kontroll::daemon::{self, Daemon};
+use kontroll::keyboard::{KeyboardModel, LedSelection};
+use kontroll::layout::{LayerKeys, Layout, LayoutError};
+#[cfg(unix)]
+use kontroll::server;
+use kontroll::session;
+#[cfg(unix)]
+use kontroll::session::Recorder;
+#[cfg(unix)]
+use kontroll::sim::Simulator;
+use kontroll::{ApiError, Kontroll, Transport};
+
+use crate::hooks::{self, Config, LayerHooks};
+use crate::shell;
+#[cfg(unix)]
+use crate::tui;
 
 #[derive(Debug, Parser)]
 #[command(name = "Kontroll", version = env!("CARGO_PKG_VERSION"))]
@@ -9,12 +40,49 @@ use kontroll::{utils, Kontroll};
 pub struct Cli {
     #[arg(short, long, name = "Server socket path or port on Windows")]
     port: Option<String>,
+    #[arg(
+        short,
+        long,
+        conflicts_with = "Server socket path or port on Windows",
+        value_name = "ENDPOINT",
+        help = "Keymapp's endpoint, either unix:///path/to/keymapp.sock or tcp://host:port"
+    )]
+    endpoint: Option<Transport>,
+    #[arg(
+        long,
+        global = true,
+        help = "Report calls refused by Keymapp as successful instead of failing"
+    )]
+    no_strict: bool,
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_name = "MILLISECONDS",
+        help = "Time allowed to connect to Keymapp and for each call"
+    )]
+    timeout: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "LED layer to draw in, when connected to a kontroll daemon"
+    )]
+    led_layer: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        default_value = "0",
+        allow_negative_numbers = true,
+        help = "Priority of the LED layer, layers with a higher priority are drawn on top"
+    )]
+    priority: i32,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand, Debug, Clone)]
-enum Commands {
+pub enum Commands {
     #[command(about = "Get the status of the currently connected keyboard")]
     Status {
         #[arg(short, long, default_value = "false")]
@@ -34,19 +102,59 @@ enum Commands {
         #[arg(short, long, required = true)]
         index: usize,
     },
+    #[command(about = "Unset a previously set layer of the currently connected keyboard")]
+    UnsetLayer {
+        #[arg(short, long, required = true)]
+        index: usize,
+    },
+    #[command(
+        about = "Set a layer and push it on top of the layer stack, within a batch or shell"
+    )]
+    PushLayer {
+        #[arg(short, long, required = true)]
+        index: usize,
+    },
+    #[command(about = "Unset the layer on top of the layer stack, within a batch or shell")]
+    PopLayer,
     #[command(about = "Sets the RGB color of a LED")]
     SetRGB {
+        #[command(flatten)]
+        leds: LedArgs,
         #[arg(short, long, required = true)]
-        led: usize,
-        #[arg(short, long, required = true)]
-        color: String,
+        color: Color,
         #[arg(short, long, default_value = "0")]
         sustain: i32,
     },
     #[command(about = "Sets the RGB color of all LEDs")]
     SetRGBAll {
         #[arg(short, long, required = true)]
-        color: String,
+        color: Color,
+        #[arg(short, long, default_value = "0")]
+        sustain: i32,
+    },
+    #[command(about = "Sets a gradient of colors across LEDs")]
+    Gradient {
+        #[arg(long, required = true, help = "Color of the first LED")]
+        from: Color,
+        #[arg(long, required = true, help = "Color of the last LED")]
+        to: Color,
+        #[arg(long, help = "Color to go through on the way, can be repeated")]
+        via: Vec<Color>,
+        #[command(flatten)]
+        leds: LedArgs,
+        #[arg(
+            short,
+            long,
+            default_value = "oklab",
+            help = "How colors are blended: linear, hsl or oklab"
+        )]
+        interpolation: Interpolation,
+        #[arg(
+            short,
+            long,
+            help = "Correct the colors for the LEDs' gamma, so dim colors look as on a screen"
+        )]
+        gamma: bool,
         #[arg(short, long, default_value = "0")]
         sustain: i32,
     },
@@ -75,172 +183,893 @@ enum Commands {
     },
     #[command(about = "Disconnect from the currently connected keyboard")]
     Disconnect,
+    #[command(about = "Run commands from a file or stdin, one per line, over a single connection")]
+    Batch {
+        #[arg(
+            default_value = "-",
+            help = "File to read the commands from, - for stdin"
+        )]
+        file: String,
+    },
+    #[command(about = "Run commands interactively over a single connection")]
+    Shell,
+    #[command(about = "Print changes of layer, keyboard and Keymapp version as they happen")]
+    Watch {
+        #[arg(short, long, help = "Print each event as a line of JSON")]
+        json: bool,
+        #[arg(
+            short,
+            long,
+            default_value = "250",
+            value_name = "MILLISECONDS",
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Time between two polls of Keymapp's status"
+        )]
+        interval: u64,
+    },
+    #[command(about = "Run commands when the keyboard enters or leaves a layer")]
+    OnLayer {
+        #[arg(
+            short,
+            long,
+            help = "Layer to run the commands for, instead of the config file"
+        )]
+        layer: Option<i32>,
+        #[arg(
+            short,
+            long,
+            requires = "layer",
+            help = "Command to run when entering the layer"
+        )]
+        exec: Option<String>,
+        #[arg(
+            long,
+            requires = "layer",
+            help = "Command to run when leaving the layer"
+        )]
+        leave: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "layer",
+            help = "Config file mapping layers to commands, defaults to kontroll/config.json in the config directory"
+        )]
+        config: Option<PathBuf>,
+        #[arg(
+            short,
+            long,
+            default_value = "250",
+            value_name = "MILLISECONDS",
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Time between two polls of Keymapp's status"
+        )]
+        interval: u64,
+    },
+    #[command(about = "Replay a session recorded by the proxy with its original timing")]
+    Replay {
+        #[arg(required = true, help = "Session file, as recorded by kontroll proxy")]
+        file: PathBuf,
+        #[arg(
+            long,
+            default_value = "1.0",
+            help = "Playback speed, 2.0 plays twice as fast, at least 0.01"
+        )]
+        speed: f64,
+        #[arg(long = "loop", help = "Replay the session until interrupted")]
+        repeat: bool,
+    },
+    #[cfg(unix)]
+    #[command(about = "Run a simulated Keymapp, to use Kontroll without a keyboard")]
+    Sim {
+        #[arg(short, long, required = true, help = "Path of the socket to listen on")]
+        socket: PathBuf,
+        #[arg(
+            short,
+            long,
+            default_value = "voyager",
+            value_parser = parse_model,
+            help = "Simulated keyboard: voyager, moonlander or ergodox"
+        )]
+        model: &'static KeyboardModel,
+        #[arg(long, help = "Start with the keyboard disconnected")]
+        disconnected: bool,
+        #[arg(
+            long,
+            help = "Draw the keyboard's LEDs in the terminal as calls arrive"
+        )]
+        tui: bool,
+    },
+    #[cfg(unix)]
+    #[command(about = "Forward calls to Keymapp, recording them as JSON lines")]
+    Proxy {
+        #[arg(short, long, required = true, help = "Path of the socket to listen on")]
+        listen: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Keymapp's socket, defaults to Keymapp's default socket"
+        )]
+        upstream: Option<PathBuf>,
+        #[arg(
+            short,
+            long,
+            help = "File to record the session to, defaults to stdout"
+        )]
+        record: Option<PathBuf>,
+    },
+    #[cfg(unix)]
+    #[command(
+        about = "Share a single connection to Keymapp between clients connecting to its socket"
+    )]
+    Daemon {
+        #[arg(
+            short,
+            long,
+            help = "Path of the socket to listen on, defaults to kontroll.sock in the runtime directory"
+        )]
+        listen: Option<PathBuf>,
+        #[arg(
+            short,
+            long,
+            help = "Keymapp's socket, defaults to Keymapp's default socket"
+        )]
+        upstream: Option<PathBuf>,
+    },
 }
 
-pub async fn run() {
-    let cli = Cli::parse();
+/// The LEDs a command applies to, by index, by position or by the key above them.
+#[derive(Debug, Clone, Args)]
+pub struct LedArgs {
+    #[arg(
+        short,
+        long,
+        visible_alias = "leds",
+        required_unless_present_any = ["key", "layer_keys"],
+        help = "LEDs in order: an index, a range such as 0-11, r2c3, row:1, col:0, left, right, thumbs or all, separated by commas"
+    )]
+    led: Option<LedSelection>,
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        requires = "layout",
+        help = "Keys whose LEDs to select, such as KC_ESC, found in the layout"
+    )]
+    key: Vec<String>,
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Layer of the layout to find the keys on"
+    )]
+    key_layer: usize,
+    #[arg(
+        long,
+        requires = "layout",
+        value_name = "LAYER[:KIND]",
+        help = "Keys assigned on a layer of the layout, or those of a kind: modifiers, layers, letters, numbers, function or transparent"
+    )]
+    layer_keys: Option<LayerKeys>,
+    #[arg(long, help = "QMK keymap.json or Oryx layout of the keyboard")]
+    layout: Option<PathBuf>,
+}
 
-    let api = Kontroll::new(cli.port).await.unwrap_or_else(|err| {
-        eprintln!("{}", err);
-        exit(1);
-    });
+/// A single command, as read by batch mode and the shell.
+#[derive(Debug, Parser)]
+#[command(no_binary_name = true)]
+pub struct Line {
+    #[command(subcommand)]
+    command: Commands,
+}
 
-    match cli.command {
-        Commands::Status { json } => match api.get_status().await {
-            Ok(status) => {
-                if json {
-                    println!("{}", serde_json::to_string_pretty(&status).unwrap());
-                } else {
-                    println!("{}", status)
+/// Splits a line into words the way a shell would: quotes keep spaces within a word, and a
+/// backslash escapes the next character outside single quotes. Spaces within parentheses don't
+/// split words either, so colors such as `rgb(10, 20, 30)` need no quotes. Lines starting with
+/// `#` are comments, without words.
+pub fn split_line(line: &str) -> Result<Vec<String>, CliError> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return Ok(Vec::new());
+    }
+    let mut words = Vec::new();
+    // `None` between words, so that `""` still makes an empty word.
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+            (Some(_), '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some(c @ ('"' | '\\')) => word.push(c),
+                    Some(c) => word.extend(['\\', c]),
+                    None => word.push('\\'),
                 }
             }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
+            (None, '\\') => word
+                .get_or_insert_with(String::new)
+                .push(chars.next().unwrap_or('\\')),
+            (None, '\'' | '"') => {
+                word.get_or_insert_with(String::new);
+                quote = Some(c);
             }
-        },
-        Commands::List => match api.list_keyboards().await {
-            Ok(keyboards) => {
-                for keyboard in keyboards.iter() {
-                    let connected = if keyboard.is_connected {
-                        "(connected)"
-                    } else {
-                        ""
-                    };
-                    println!("{}: {} {}", keyboard.id, keyboard.friendly_name, connected);
+            (None, c) if c.is_whitespace() && depth == 0 => words.extend(word.take()),
+            (_, c) => {
+                if quote.is_none() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
                 }
+                word.get_or_insert_with(String::new).push(c);
             }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
-            }
-        },
-        Commands::Connect { index } => match api.connect(index).await {
-            Ok(_) => {
-                println!("Connected to keyboard {}", index);
+        }
+    }
+    if let Some(quote) = quote {
+        return Err(CliError::Usage(format!("Missing closing {} quote", quote)));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Parses a line with the same grammar as the subcommands, `None` for blank lines and
+/// `#` comments. Words are split with [`split_line`].
+pub fn parse_line(line: &str) -> Result<Option<Commands>, CliError> {
+    parse_words(split_line(line)?)
+}
+
+/// Parses the words of a line, see [`parse_line`].
+pub fn parse_words(words: Vec<String>) -> Result<Option<Commands>, CliError> {
+    if words.is_empty() {
+        return Ok(None);
+    }
+    match Line::try_parse_from(words) {
+        Ok(line) => Ok(Some(line.command)),
+        // Asking for help is the one case where the whole message is wanted.
+        Err(e) if e.kind() == ErrorKind::DisplayHelp => {
+            Err(CliError::Usage(e.to_string().trim_end().to_string()))
+        }
+        // Keep clap's summary, the usage that follows is for the command line.
+        Err(e) => Err(CliError::Usage(
+            e.to_string()
+                .lines()
+                .take_while(|line| !line.is_empty())
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(" ")
+                .trim_start_matches("error: ")
+                .to_string(),
+        )),
+    }
+}
+
+/// Errors reported by the CLI, each class of failure exits with its own code so scripts can
+/// branch on it.
+#[derive(Debug)]
+pub enum CliError {
+    Api(ApiError),
+    Io(io::Error),
+    Usage(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Io(_) => 1,
+            CliError::Usage(_) | CliError::Api(ApiError::InvalidArgument(_)) => 2,
+            CliError::Api(ApiError::ConfigDirNotFound | ApiError::SocketNotFound(_)) => 3,
+            CliError::Api(ApiError::InvalidEndpoint(_) | ApiError::Connect(_)) => 4,
+            CliError::Api(ApiError::Timeout { .. }) => 5,
+            CliError::Api(ApiError::Rpc { .. }) => 6,
+            CliError::Api(ApiError::Rejected { .. }) => 7,
+        }
+    }
+}
+
+impl From<ApiError> for CliError {
+    fn from(e: ApiError) -> Self {
+        CliError::Api(e)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Api(e) => write!(f, "{}", e),
+            CliError::Io(e) => write!(f, "{}", e),
+            CliError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub async fn run() {
+    let cli = Cli::parse();
+
+    // The simulator and the proxy serve Keymapp's API instead of connecting to it.
+    #[cfg(unix)]
+    if let Some(result) = serve(&cli.command, cli.timeout).await {
+        if let Err(e) = result {
+            fail(e);
+        }
+        return;
+    }
+
+    // The layer stack lives in the client, a one-shot call would forget it on exit.
+    if matches!(cli.command, Commands::PushLayer { .. } | Commands::PopLayer) {
+        fail(CliError::Usage(
+            "push-layer and pop-layer can only be run within a batch or shell".to_string(),
+        ));
+    }
+
+    let mut builder = Kontroll::builder().strict(!cli.no_strict);
+    if let Some(port) = cli.port {
+        builder = builder.port(port);
+    }
+    if let Some(transport) = cli.endpoint {
+        builder = builder.transport(transport);
+    }
+    if let Some(timeout) = cli.timeout {
+        let timeout = Duration::from_millis(timeout);
+        builder = builder.connect_timeout(timeout).request_timeout(timeout);
+    }
+    if let Some(layer) = cli.led_layer {
+        builder = builder.led_layer(layer, cli.priority);
+    }
+    let api = match builder.connect().await {
+        Ok(api) => api,
+        Err(e) => fail(e.into()),
+    };
+
+    match cli.command {
+        Commands::Batch { file } => exit(run_batch(&api, &file).await),
+        Commands::Shell => exit(shell::run(&api).await),
+        Commands::Watch { json, interval } => {
+            tokio::select! {
+                _ = watch(&api, json, Duration::from_millis(interval)) => {}
+                _ = tokio::signal::ctrl_c() => {}
             }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
+            return;
+        }
+        Commands::OnLayer {
+            layer,
+            exec,
+            leave,
+            config,
+            interval,
+        } => {
+            let hooks = match layer_hooks(layer, exec, leave, config) {
+                Ok(hooks) => hooks,
+                Err(e) => fail(e),
+            };
+            tokio::select! {
+                _ = hooks::run(&api, &hooks, Duration::from_millis(interval)) => {}
+                _ = tokio::signal::ctrl_c() => {}
             }
-        },
-        Commands::ConnectAny => match api.connect_any().await {
-            Ok(_) => {
-                println!("Connected to the first keyboard detected by keymapp");
+            return;
+        }
+        Commands::Replay {
+            file,
+            speed,
+            repeat: true,
+        } => {
+            tokio::select! {
+                result = replay_loop(&api, &file, speed) => match result {
+                    Ok(output) => println!("{}", output),
+                    Err(e) => fail(e),
+                },
+                _ = tokio::signal::ctrl_c() => {}
             }
+            return;
+        }
+        _ => {}
+    }
+    match execute(&api, cli.command).await {
+        Ok(output) => println!("{}", output),
+        Err(e) => fail(e),
+    }
+}
+
+fn read_session(file: &Path) -> Result<Vec<session::Entry>, CliError> {
+    let file = std::fs::File::open(file).map_err(CliError::Io)?;
+    session::read(io::BufReader::new(file)).map_err(CliError::Io)
+}
+
+/// Replays the session in `file` over and over, until interrupted or the session turns out
+/// to be empty.
+async fn replay_loop(api: &Kontroll, file: &Path, speed: f64) -> Result<String, CliError> {
+    let entries = read_session(file)?;
+    let mut replayed = 0;
+    loop {
+        let calls = session::replay(api, &entries, speed).await?;
+        if calls == 0 {
+            return Ok(format!("Replayed {} calls", replayed));
+        }
+        replayed += calls;
+    }
+}
+
+/// Runs the commands read from `file` one after the other, printing each line's result
+/// prefixed with its number. Failed lines don't stop the batch, returns the exit code of the
+/// first failure.
+async fn run_batch(api: &Kontroll, file: &str) -> i32 {
+    let input: Box<dyn AsyncBufRead + Unpin + Send> = if file == "-" {
+        Box::new(BufReader::new(tokio::io::stdin()))
+    } else {
+        match tokio::fs::File::open(file).await {
+            Ok(file) => Box::new(BufReader::new(file)),
             Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
+                eprintln!("{}: {}", file, e);
+                return CliError::Io(e).exit_code();
             }
-        },
-        Commands::Disconnect => match api.disconnect().await {
-            Ok(_) => {
-                println!("Disconnected from the currently connected keyboard");
+        }
+    };
+
+    let mut lines = input.lines();
+    let mut number = 0;
+    let mut code = 0;
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return code,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                return CliError::Io(e).exit_code();
             }
+        };
+        number += 1;
+        let result = match parse_line(&line) {
+            Ok(Some(command)) => execute(api, command).await,
+            Ok(None) => continue,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) => println!("{}: {}", number, output),
             Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
+                eprintln!("{}: {}", number, e);
+                if code == 0 {
+                    code = e.exit_code();
+                }
             }
-        },
-        Commands::SetLayer { index } => match api.set_layer(index).await {
-            Ok(_) => {
-                println!("Layer set to {}", index);
+        }
+    }
+}
+
+/// Returns the hooks given on the command line, or else the ones of the config file.
+fn layer_hooks(
+    layer: Option<i32>,
+    exec: Option<String>,
+    leave: Option<String>,
+    config: Option<PathBuf>,
+) -> Result<BTreeMap<i32, LayerHooks>, CliError> {
+    if let Some(layer) = layer {
+        if exec.is_none() && leave.is_none() {
+            return Err(CliError::Usage(
+                "--layer needs a command to run, given with --exec or --leave".to_string(),
+            ));
+        }
+        let hooks = LayerHooks {
+            on_enter: exec,
+            on_leave: leave,
+        };
+        return Ok(BTreeMap::from([(layer, hooks)]));
+    }
+    let path = config.or_else(Config::default_path).ok_or_else(|| {
+        CliError::Usage("no config file, pass the hooks with --layer or --config".to_string())
+    })?;
+    Ok(Config::load(&path)?.layers)
+}
+
+/// Prints Keymapp's status changes one per line, forever.
+async fn watch(api: &Kontroll, json: bool, interval: Duration) {
+    let events = api.watch_status(interval);
+    tokio::pin!(events);
+    while let Some(event) = events.next().await {
+        if json {
+            println!("{}", serde_json::to_string(&event).unwrap());
+        } else {
+            println!("{}", event);
+        }
+    }
+}
+
+/// Runs the commands serving Keymapp's API until interrupted, returns `None` for other commands.
+#[cfg(unix)]
+async fn serve(command: &Commands, timeout: Option<u64>) -> Option<Result<(), CliError>> {
+    let result = match command.clone() {
+        Commands::Sim {
+            socket,
+            model,
+            disconnected,
+            tui,
+        } => run_sim(socket, model, disconnected, tui).await,
+        Commands::Proxy {
+            listen,
+            upstream,
+            record,
+        } => run_proxy(listen, upstream, record, timeout.map(Duration::from_millis)).await,
+        Commands::Daemon { listen, upstream } => {
+            run_daemon(listen, upstream, timeout.map(Duration::from_millis)).await
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Parses the model of the simulated keyboard from its name.
+#[cfg(unix)]
+fn parse_model(name: &str) -> Result<&'static KeyboardModel, String> {
+    kontroll::keyboard::from_name(name).ok_or_else(|| {
+        format!(
+            "unknown model {}, expected voyager, moonlander or ergodox",
+            name
+        )
+    })
+}
+
+/// Serves a simulated Keymapp until interrupted, removing its socket afterwards.
+#[cfg(unix)]
+async fn run_sim(
+    socket: PathBuf,
+    model: &'static KeyboardModel,
+    disconnected: bool,
+    tui: bool,
+) -> Result<(), CliError> {
+    let sim = Simulator::with_model(model);
+    if !disconnected {
+        sim.set_connected_index(0);
+    }
+    let listener = tokio::net::UnixListener::bind(&socket).map_err(CliError::Io)?;
+    if !tui {
+        println!(
+            "Simulating a {} on {}, press Ctrl+C to stop",
+            model.friendly_name(),
+            socket.display()
+        );
+    }
+    let result = tokio::select! {
+        result = sim.clone().serve(listener) => result,
+        result = tokio::signal::ctrl_c() => result,
+        result = tui::run(&sim, &socket), if tui => result,
+    };
+    if tui {
+        tui::restore();
+    }
+    let _ = std::fs::remove_file(&socket);
+    result.map_err(CliError::Io)
+}
+
+/// Forwards calls to Keymapp and records them until interrupted, removing its socket
+/// afterwards.
+#[cfg(unix)]
+async fn run_proxy(
+    listen: PathBuf,
+    upstream: Option<PathBuf>,
+    record: Option<PathBuf>,
+    timeout: Option<Duration>,
+) -> Result<(), CliError> {
+    let transport = Transport::platform_default(upstream.map(|p| p.display().to_string()))?;
+    let upstream = transport.to_string();
+    let backend =
+        GrpcBackend::connect(transport, timeout.or(Some(DEFAULT_CONNECT_TIMEOUT))).await?;
+    let out: Box<dyn Write + Send> = match &record {
+        Some(path) => Box::new(io::BufWriter::new(
+            std::fs::File::create(path).map_err(CliError::Io)?,
+        )),
+        None => Box::new(io::stdout()),
+    };
+    let listener = tokio::net::UnixListener::bind(&listen).map_err(CliError::Io)?;
+    // The session may be written to stdout, keep it clean.
+    eprintln!(
+        "Forwarding calls from {} to {}, press Ctrl+C to stop",
+        listen.display(),
+        upstream
+    );
+    let result = tokio::select! {
+        result = server::serve(Recorder::new(backend, out), listener) => result,
+        result = tokio::signal::ctrl_c() => result,
+    };
+    let _ = std::fs::remove_file(&listen);
+    result.map_err(CliError::Io)
+}
+
+/// Shares the connection to Keymapp with the clients of its socket until interrupted, removing
+/// its socket afterwards.
+#[cfg(unix)]
+async fn run_daemon(
+    listen: Option<PathBuf>,
+    upstream: Option<PathBuf>,
+    timeout: Option<Duration>,
+) -> Result<(), CliError> {
+    let listen = listen.unwrap_or_else(daemon::default_socket);
+    let transport = Transport::platform_default(upstream.map(|p| p.display().to_string()))?;
+    let upstream = transport.to_string();
+    let backend =
+        GrpcBackend::connect(transport, timeout.or(Some(DEFAULT_CONNECT_TIMEOUT))).await?;
+    let listener = match tokio::net::UnixListener::bind(&listen) {
+        // A socket left behind by a daemon that didn't stop cleanly can be replaced.
+        Err(e)
+            if e.kind() == io::ErrorKind::AddrInUse
+                && std::os::unix::net::UnixStream::connect(&listen).is_err() =>
+        {
+            std::fs::remove_file(&listen).map_err(CliError::Io)?;
+            tokio::net::UnixListener::bind(&listen)
+        }
+        result => result,
+    }
+    .map_err(CliError::Io)?;
+    println!(
+        "Sharing the connection to {} on {}, press Ctrl+C to stop",
+        upstream,
+        listen.display()
+    );
+    let result = tokio::select! {
+        result = Daemon::new(backend).serve(listener) => result,
+        result = tokio::signal::ctrl_c() => result,
+    };
+    let _ = std::fs::remove_file(&listen);
+    result.map_err(CliError::Io)
+}
+
+fn fail(e: CliError) -> ! {
+    eprintln!("{}", e);
+    exit(e.exit_code());
+}
+
+/// Returns the model of the connected keyboard, to find LEDs by their position.
+async fn connected_model(api: &Kontroll) -> Result<&'static KeyboardModel, CliError> {
+    let status = api.get_status().await?;
+    let keyboard = status.keyboard().ok_or_else(|| {
+        CliError::Usage("Selecting LEDs by position needs a connected keyboard".to_string())
+    })?;
+    keyboard.model().ok_or_else(|| {
+        CliError::Usage(format!(
+            "The layout of the {} isn't known, select LEDs by index",
+            keyboard.friendly_name()
+        ))
+    })
+}
+
+/// Returns the selected LEDs in order, each once. Keymapp is only asked for the connected
+/// keyboard when the selection depends on its layout.
+async fn resolve_leds(api: &Kontroll, leds: &LedArgs) -> Result<Vec<usize>, CliError> {
+    let by_key = !leds.key.is_empty() || leds.layer_keys.is_some();
+    let model = match &leds.led {
+        Some(selection) if selection.needs_model() => Some(connected_model(api).await?),
+        _ if by_key => Some(connected_model(api).await?),
+        _ => None,
+    };
+    let mut selected = match &leds.led {
+        Some(selection) => selection.resolve(model).map_err(CliError::Usage)?,
+        None => Vec::new(),
+    };
+
+    if let (Some(path), Some(model)) = (&leds.layout, model.filter(|_| by_key)) {
+        let layout_error = |e: LayoutError| match e {
+            LayoutError::Io(e) => CliError::Io(e),
+            e => CliError::Usage(format!("{}: {}", path.display(), e)),
+        };
+        let layout = Layout::load(path).map_err(layout_error)?;
+        for key in &leds.key {
+            let found = layout
+                .key_leds(model, leds.key_layer, key)
+                .map_err(layout_error)?;
+            if found.is_empty() {
+                return Err(CliError::Usage(format!(
+                    "{} isn't on layer {} of {}",
+                    key,
+                    leds.key_layer,
+                    path.display()
+                )));
             }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
+            selected.extend(found);
+        }
+        if let Some(layer_keys) = &leds.layer_keys {
+            selected.extend(layer_keys.leds(&layout, model).map_err(layout_error)?);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    selected.retain(|&led| seen.insert(led));
+    if selected.is_empty() {
+        return Err(CliError::Usage("No LED selected".to_string()));
+    }
+    Ok(selected)
+}
+
+/// Runs a single command, returning the message to print on success.
+pub async fn execute(api: &Kontroll, command: Commands) -> Result<String, CliError> {
+    let output = match command {
+        Commands::Status { json } => {
+            let status = api.get_status().await?;
+            if json {
+                serde_json::to_string_pretty(&status).unwrap()
+            } else {
+                status.to_string()
             }
+        }
+        Commands::List => {
+            let keyboards = api.list_keyboards().await?;
+            keyboards
+                .iter()
+                .map(|keyboard| {
+                    let connected = if keyboard.is_connected {
+                        "(connected)"
+                    } else {
+                        ""
+                    };
+                    format!("{}: {} {}", keyboard.id, keyboard.friendly_name, connected)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Commands::Connect { index } => {
+            api.connect(index).await?;
+            format!("Connected to keyboard {}", index)
+        }
+        Commands::ConnectAny => {
+            api.connect_any().await?;
+            "Connected to the first keyboard detected by keymapp".to_string()
+        }
+        Commands::Disconnect => {
+            api.disconnect().await?;
+            "Disconnected from the currently connected keyboard".to_string()
+        }
+        Commands::Replay { repeat: true, .. } => {
+            return Err(CliError::Usage(
+                "replay --loop can only be run on its own".to_string(),
+            ))
+        }
+        Commands::Replay { file, speed, .. } => {
+            let entries = read_session(&file)?;
+            let replayed = session::replay(api, &entries, speed).await?;
+            format!("Replayed {} calls", replayed)
+        }
+        Commands::Batch { .. }
+        | Commands::Shell
+        | Commands::Watch { .. }
+        | Commands::OnLayer { .. } => {
+            return Err(CliError::Usage(
+                "batch, shell, watch and on-layer can only be run on their own".to_string(),
+            ))
+        }
+        #[cfg(unix)]
+        Commands::Sim { .. } | Commands::Proxy { .. } | Commands::Daemon { .. } => {
+            return Err(CliError::Usage(
+                "sim, proxy and daemon can only be run on their own".to_string(),
+            ))
+        }
+        Commands::SetLayer { index } => {
+            api.set_layer(index).await?;
+            format!("Layer set to {}", index)
+        }
+        Commands::UnsetLayer { index } => {
+            api.unset_layer(index).await?;
+            format!("Layer {} unset", index)
+        }
+        Commands::PushLayer { index } => {
+            api.push_layer(index).await?;
+            format!("Layer {} pushed", index)
+        }
+        Commands::PopLayer => match api.pop_layer().await? {
+            Some(index) => format!("Layer {} popped", index),
+            None => "No layer to pop".to_string(),
         },
         Commands::SetRGB {
-            led,
+            leds,
             color,
             sustain,
         } => {
-            let (r, g, b) = match utils::hex_to_rgb(&color) {
-                Ok(rgb) => rgb,
-                Err(_) => {
-                    eprintln!("{} is not a valid hex color", color);
-                    exit(1);
-                }
-            };
-
-            match api.set_rgb_led(led, r, g, b, sustain).await {
-                Ok(_) => {
-                    println!("LED {} set to color {}", led, color);
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    exit(1);
-                }
+            let leds = resolve_leds(api, &leds).await?;
+            let (r, g, b) = color.rgb();
+            for &led in &leds {
+                api.set_rgb_led(led, r, g, b, sustain).await?;
+            }
+            match leds[..] {
+                [led] => format!("LED {} set to color {}", led, color),
+                _ => format!("{} LEDs set to color {}", leds.len(), color),
             }
         }
         Commands::SetRGBAll { color, sustain } => {
-            let (r, g, b) = match utils::hex_to_rgb(&color) {
-                Ok(rgb) => rgb,
-                Err(_) => {
-                    eprintln!("{} is not a valid hex color", color);
-                    exit(1);
-                }
-            };
-
-            match api.set_rgb_all(r, g, b, sustain).await {
-                Ok(_) => {
-                    println!("All LEDs set to color {}", color);
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    exit(1);
-                }
-            }
+            let (r, g, b) = color.rgb();
+            api.set_rgb_all(r, g, b, sustain).await?;
+            format!("All LEDs set to color {}", color)
         }
-        Commands::RestoreRGBLeds {} => match api.restore_rgb_leds().await {
-            Ok(_) => {
-                println!("All LEDs restored to their default color");
-            }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
+        Commands::Gradient {
+            from,
+            to,
+            via,
+            leds,
+            interpolation,
+            gamma,
+            sustain,
+        } => {
+            let leds = resolve_leds(api, &leds).await?;
+            let colors = std::iter::once(from).chain(via).chain([to]);
+            let gradient = Gradient::new(colors).interpolation(interpolation);
+            for (&led, color) in leds.iter().zip(gradient.colors(leds.len())) {
+                let color = if gamma {
+                    color.gamma_corrected()
+                } else {
+                    color
+                };
+                let (r, g, b) = color.rgb();
+                api.set_rgb_led(led, r, g, b, sustain).await?;
             }
-        },
+            format!(
+                "Gradient from {} to {} set on {} LEDs",
+                from,
+                to,
+                leds.len()
+            )
+        }
+        Commands::RestoreRGBLeds {} => {
+            api.restore_rgb_leds().await?;
+            "All LEDs restored to their default color".to_string()
+        }
         Commands::SetStatusLed { led, off, sustain } => {
             let on = !off;
-            match api.set_status_led(led, on, sustain).await {
-                Ok(_) => {
-                    let state = if on { "on" } else { "off" };
-                    println!("Status LED {} turned {}", led, state);
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    exit(1);
-                }
-            }
+            api.set_status_led(led, on, sustain).await?;
+            let state = if on { "on" } else { "off" };
+            format!("Status LED {} turned {}", led, state)
         }
-        Commands::RestoreStatusLeds {} => match api.restore_status_leds().await {
-            Ok(_) => {
-                println!("All status LEDs restored to their default state");
-            }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
-            }
-        },
-        Commands::IncreaseBrightness { steps } => match api.update_brightness(true, steps).await {
-            Ok(_) => {
-                println!("Brightness increased");
-            }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
-            }
-        },
-        Commands::DecreaseBrightness { steps } => match api.update_brightness(false, steps).await {
-            Ok(_) => {
-                println!("Brightness decreased");
-            }
-            Err(e) => {
-                eprintln!("{}", e);
-                exit(1);
-            }
-        },
+        Commands::RestoreStatusLeds {} => {
+            api.restore_status_leds().await?;
+            "All status LEDs restored to their default state".to_string()
+        }
+        Commands::IncreaseBrightness { steps } => {
+            api.update_brightness(true, steps).await?;
+            "Brightness increased".to_string()
+        }
+        Commands::DecreaseBrightness { steps } => {
+            api.update_brightness(false, steps).await?;
+            "Brightness decreased".to_string()
+        }
+    };
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines() {
+        let split = |line: &str| split_line(line).unwrap();
+        assert_eq!(
+            split("  set-layer   --index 2 "),
+            ["set-layer", "--index", "2"]
+        );
+        assert_eq!(
+            split("set-rgb-all --color rgb(10, 20, 30)"),
+            ["set-rgb-all", "--color", "rgb(10, 20, 30)"]
+        );
+        assert_eq!(
+            split(r#"on-layer --layer 1 --exec "notify-send hi""#),
+            ["on-layer", "--layer", "1", "--exec", "notify-send hi"]
+        );
+        assert_eq!(
+            split(r#"a 'b "c"' "d \"e\" \f" g\ h """#),
+            ["a", r#"b "c""#, r#"d "e" \f"#, "g h", ""]
+        );
+        assert!(split("# a comment").is_empty());
+        assert!(split("").is_empty());
+        assert!(split_line("set-rgb --color 'red").is_err());
+    }
+
+    #[test]
+    fn parse_quoted_arguments() {
+        let command = parse_line(r#"on-layer --layer 1 --exec "notify-send hi""#);
+        assert!(matches!(
+            command,
+            Ok(Some(Commands::OnLayer { exec: Some(exec), .. })) if exec == "notify-send hi"
+        ));
+        let command = parse_line("set-rgb-all --color rgb(10, 20, 30)");
+        assert!(matches!(
+            command,
+            Ok(Some(Commands::SetRGBAll { color, .. })) if color == Color::new(10, 20, 30)
+        ));
+        assert!(matches!(parse_line("  # comment"), Ok(None)));
     }
 }
@@ -0,0 +1,710 @@
+//! Colors for the keyboard's LEDs: parsing, dimming, blending and gradients.
+//!
+//! ```rust
+//! use kontroll::color::{Color, Gradient};
+//!
+//! let from: Color = "#ff00ff".parse().unwrap();
+//! let gradient = Gradient::new([from, from.scale(0.1)]);
+//! for (led, color) in gradient.colors(12).into_iter().enumerate() {
+//!     let (r, g, b) = color.gamma_corrected().rgb();
+//!     // api.set_rgb_led(led, r, g, b, 0).await?;
+//! #   let _ = (led, r, g, b);
+//! }
+//! ```
+use std::fmt;
+use std::str::FromStr;
+
+/// An RGB color, as set on the keyboard's LEDs.
+///
+/// Colors can be parsed from `#rgb`, `#rrggbb` (the `#` is optional), `rgb(r, g, b)`,
+/// `hsl(h, s%, l%)`, `hsv(h, s%, v%)`, CSS color names or `off`:
+///
+/// ```rust
+/// use kontroll::color::Color;
+///
+/// let color: Color = "hsl(120, 100%, 50%)".parse().unwrap();
+/// assert_eq!(color, Color::new(0, 255, 0));
+/// assert_eq!("#0f0".parse::<Color>().unwrap(), color);
+/// assert_eq!(color.to_string(), "#00ff00");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// The LED turned off.
+    pub const OFF: Color = Color::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Returns the color as a tuple of (r, g, b).
+    pub fn rgb(self) -> (u8, u8, u8) {
+        (self.r, self.g, self.b)
+    }
+
+    /// Converts a color given as hue in degrees, saturation and lightness between 0 and 1.
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Self {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        Self::from_chroma(h, c, l - c / 2.0)
+    }
+
+    /// Converts a color given as hue in degrees, saturation and value between 0 and 1.
+    pub fn from_hsv(h: f64, s: f64, v: f64) -> Self {
+        let c = v * s;
+        Self::from_chroma(h, c, v - c)
+    }
+
+    // The part shared by HSL and HSV, from the hue, chroma and the lightness to add to each
+    // component.
+    fn from_chroma(h: f64, c: f64, m: f64) -> Self {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let channel = |v: f64| ((v + m).clamp(0.0, 1.0) * 255.0).round() as u8;
+        Self::new(channel(r), channel(g), channel(b))
+    }
+
+    pub(crate) fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.is_ascii() {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        match hex.len() {
+            3 => Some(Self::new(digit(0)? * 17, digit(1)? * 17, digit(2)? * 17)),
+            6 => Some(Self::new(pair(0)?, pair(2)?, pair(4)?)),
+            _ => None,
+        }
+    }
+
+    fn from_named(name: &str) -> Option<Self> {
+        let index = NAMED_COLORS
+            .binary_search_by_key(&name, |(name, _)| name)
+            .ok()?;
+        let [_, r, g, b] = NAMED_COLORS[index].1.to_be_bytes();
+        Some(Self::new(r, g, b))
+    }
+
+    // Parses `name(a, b, c)`, with the arguments separated by commas or spaces.
+    fn from_function(s: &str) -> Option<Self> {
+        let (name, args) = s.strip_suffix(')')?.split_once('(')?;
+        let args: Vec<&str> = args
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .collect();
+        let [a, b, c] = args[..] else {
+            return None;
+        };
+        match name.trim() {
+            "rgb" => Some(Self::new(channel(a)?, channel(b)?, channel(c)?)),
+            "hsl" => Some(Self::from_hsl(hue(a)?, percent(b)?, percent(c)?)),
+            "hsv" => Some(Self::from_hsv(hue(a)?, percent(b)?, percent(c)?)),
+            _ => None,
+        }
+    }
+
+    /// Scales the brightness of the color, dimming it below 1.
+    ///
+    /// ```rust
+    /// use kontroll::color::Color;
+    ///
+    /// // A dim background for a bright magenta.
+    /// assert_eq!(Color::new(255, 0, 255).scale(0.025), Color::new(6, 0, 6));
+    /// ```
+    pub fn scale(self, factor: f64) -> Self {
+        let channel = |v: u8| (v as f64 * factor).round().clamp(0.0, 255.0) as u8;
+        Self::new(channel(self.r), channel(self.g), channel(self.b))
+    }
+
+    /// Draws `color` over this color with an opacity between 0 and 1.
+    pub fn blend(self, color: Color, alpha: f64) -> Self {
+        self.interpolate(color, alpha, Interpolation::Linear)
+    }
+
+    /// Returns the color at `t` between this color, at 0, and `other`, at 1.
+    pub fn interpolate(self, other: Color, t: f64, interpolation: Interpolation) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        match interpolation {
+            Interpolation::Linear => {
+                let channel = |a: u8, b: u8| lerp(a as f64, b as f64, t).round() as u8;
+                Self::new(
+                    channel(self.r, other.r),
+                    channel(self.g, other.g),
+                    channel(self.b, other.b),
+                )
+            }
+            Interpolation::Hsl => {
+                let (h1, s1, l1) = self.to_hsl();
+                let (h2, s2, l2) = other.to_hsl();
+                // Grays have no hue, they take the other color's so the hue doesn't sweep.
+                let (h1, h2) = match (s1 == 0.0, s2 == 0.0) {
+                    (true, false) => (h2, h2),
+                    (false, true) => (h1, h1),
+                    _ => (h1, h2),
+                };
+                // Around the shortest way of the hue circle.
+                let delta = (h2 - h1 + 540.0).rem_euclid(360.0) - 180.0;
+                Self::from_hsl(h1 + delta * t, lerp(s1, s2, t), lerp(l1, l2, t))
+            }
+            Interpolation::Oklab => {
+                let [l1, a1, b1] = self.to_oklab();
+                let [l2, a2, b2] = other.to_oklab();
+                Self::from_oklab([lerp(l1, l2, t), lerp(a1, a2, t), lerp(b1, b2, t)])
+            }
+        }
+    }
+
+    /// Corrects the color for the [`LED_GAMMA`] of the keyboard's LEDs, so a gradient or a
+    /// dimmed color looks the way it does on a screen.
+    pub fn gamma_corrected(self) -> Self {
+        self.gamma(LED_GAMMA)
+    }
+
+    /// Raises each channel, between 0 and 1, to the power of `gamma`.
+    pub fn gamma(self, gamma: f64) -> Self {
+        let channel = |v: u8| ((v as f64 / 255.0).powf(gamma) * 255.0).round() as u8;
+        Self::new(channel(self.r), channel(self.g), channel(self.b))
+    }
+
+    /// Returns the hue in degrees, the saturation and the lightness between 0 and 1.
+    pub fn to_hsl(self) -> (f64, f64, f64) {
+        let [r, g, b] = [self.r, self.g, self.b].map(|v| v as f64 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let c = max - min;
+        if c == 0.0 {
+            return (0.0, 0.0, l);
+        }
+        let h = if max == r {
+            ((g - b) / c).rem_euclid(6.0)
+        } else if max == g {
+            (b - r) / c + 2.0
+        } else {
+            (r - g) / c + 4.0
+        };
+        (h * 60.0, c / (1.0 - (2.0 * l - 1.0).abs()), l)
+    }
+
+    fn to_oklab(self) -> [f64; 3] {
+        let [r, g, b] = [self.r, self.g, self.b].map(|v| to_linear(v as f64 / 255.0));
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+        [
+            0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        ]
+    }
+
+    fn from_oklab([l, a, b]: [f64; 3]) -> Self {
+        let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+        let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+        let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+        let channel = |v: f64| (from_linear(v).clamp(0.0, 1.0) * 255.0).round() as u8;
+        Self::new(
+            channel(4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_),
+            channel(-1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_),
+            channel(-0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_),
+        )
+    }
+}
+
+// An RGB channel, from 0 to 255 or as a percentage.
+fn channel(s: &str) -> Option<u8> {
+    match s.strip_suffix('%') {
+        Some(percent) => {
+            let percent: f64 = percent.parse().ok()?;
+            (0.0..=100.0)
+                .contains(&percent)
+                .then(|| (percent / 100.0 * 255.0).round() as u8)
+        }
+        None => s.parse().ok(),
+    }
+}
+
+// A hue in degrees, wrapping around.
+fn hue(s: &str) -> Option<f64> {
+    let hue: f64 = s.strip_suffix("deg").unwrap_or(s).parse().ok()?;
+    hue.is_finite().then_some(hue)
+}
+
+// A percentage between 0 and 100, the `%` is optional. Returns a fraction between 0 and 1.
+fn percent(s: &str) -> Option<f64> {
+    let percent: f64 = s.strip_suffix('%').unwrap_or(s).parse().ok()?;
+    (0.0..=100.0).contains(&percent).then(|| percent / 100.0)
+}
+
+/// The error returned when a color can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseColorError {
+    input: String,
+}
+
+impl ParseColorError {
+    pub(crate) fn new(input: &str) -> Self {
+        Self {
+            input: input.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is not a valid color, use #rgb, #rrggbb, rgb(r, g, b), hsl(h, s%, l%), hsv(h, s%, v%), a CSS color name or off",
+            self.input
+        )
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let color = s.trim().to_ascii_lowercase();
+        let parsed = match color.as_str() {
+            "off" => Some(Color::OFF),
+            c if c.ends_with(')') => Self::from_function(c),
+            c => Self::from_named(c).or_else(|| Self::from_hex(c)),
+        };
+        parsed.ok_or_else(|| ParseColorError::new(s))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Self::new(r, g, b)
+    }
+}
+
+impl From<Color> for (u8, u8, u8) {
+    fn from(color: Color) -> Self {
+        color.rgb()
+    }
+}
+
+/// The gamma of the keyboard's LEDs: their brightness grows linearly with the value set, while
+/// the eye is far more sensitive to changes among dim colors.
+pub const LED_GAMMA: f64 = 2.8;
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// sRGB's transfer functions, between gamma encoded and linear light.
+fn to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// How colors in between two colors are computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Each of the red, green and blue channels on its own, dull in the middle of
+    /// complementary colors.
+    Linear,
+    /// Around the hue circle, keeping the colors saturated.
+    Hsl,
+    /// In the OKLab color space, perceptually even.
+    #[default]
+    Oklab,
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Interpolation::Linear => "linear",
+            Interpolation::Hsl => "hsl",
+            Interpolation::Oklab => "oklab",
+        })
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" | "rgb" => Ok(Interpolation::Linear),
+            "hsl" => Ok(Interpolation::Hsl),
+            "oklab" => Ok(Interpolation::Oklab),
+            _ => Err(format!(
+                "unknown interpolation {}, expected linear, hsl or oklab",
+                s
+            )),
+        }
+    }
+}
+
+/// Colors blending into each other, from position 0 to 1.
+///
+/// ```rust
+/// use kontroll::color::{Color, Gradient, Interpolation};
+///
+/// let gradient = Gradient::new(["red", "lime", "blue"].map(|c| c.parse().unwrap()))
+///     .interpolation(Interpolation::Linear);
+/// assert_eq!(gradient.at(0.5), Color::new(0, 255, 0));
+/// assert_eq!(gradient.colors(5)[1], Color::new(128, 128, 0));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Gradient {
+    // Sorted by position.
+    stops: Vec<(f64, Color)>,
+    interpolation: Interpolation,
+}
+
+impl Gradient {
+    /// Spreads the colors evenly, the first one at 0 and the last one at 1.
+    pub fn new(colors: impl IntoIterator<Item = Color>) -> Self {
+        let colors: Vec<Color> = colors.into_iter().collect();
+        let last = colors.len().saturating_sub(1).max(1) as f64;
+        Self::with_stops(
+            colors
+                .into_iter()
+                .enumerate()
+                .map(|(i, color)| (i as f64 / last, color)),
+        )
+    }
+
+    /// Places each color at its position, between 0 and 1.
+    pub fn with_stops(stops: impl IntoIterator<Item = (f64, Color)>) -> Self {
+        let mut stops: Vec<(f64, Color)> = stops
+            .into_iter()
+            .map(|(position, color)| (position.clamp(0.0, 1.0), color))
+            .collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            stops,
+            interpolation: Interpolation::default(),
+        }
+    }
+
+    /// Sets how the colors between two stops are computed, OKLab by default.
+    pub fn interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Returns the color at a position between 0 and 1, off for a gradient without colors.
+    pub fn at(&self, position: f64) -> Color {
+        let next = self.stops.partition_point(|(p, _)| *p < position);
+        match (self.stops.get(next.wrapping_sub(1)), self.stops.get(next)) {
+            (Some(&(start, from)), Some(&(end, to))) => {
+                from.interpolate(to, (position - start) / (end - start), self.interpolation)
+            }
+            (Some(&(_, color)), None) | (None, Some(&(_, color))) => color,
+            (None, None) => Color::OFF,
+        }
+    }
+
+    /// Returns `count` colors evenly spaced along the gradient, from its start to its end.
+    pub fn colors(&self, count: usize) -> Vec<Color> {
+        let last = count.saturating_sub(1).max(1) as f64;
+        (0..count).map(|i| self.at(i as f64 / last)).collect()
+    }
+}
+
+// The CSS named colors, sorted by name.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_colors() {
+        let parse = |s: &str| s.parse::<Color>().map(Color::rgb);
+        assert_eq!(parse("#f80"), Ok((255, 136, 0)));
+        assert_eq!(parse("FF8800"), Ok((255, 136, 0)));
+        assert_eq!(parse("rgb(255, 136, 0)"), Ok((255, 136, 0)));
+        assert_eq!(parse("rgb(100% 0% 50%)"), Ok((255, 0, 128)));
+        assert_eq!(parse("hsl(0, 100%, 50%)"), Ok((255, 0, 0)));
+        assert_eq!(parse("hsl(240deg, 100%, 25%)"), Ok((0, 0, 128)));
+        assert_eq!(parse("hsv(120, 100%, 100%)"), Ok((0, 255, 0)));
+        assert_eq!(parse("hsv(-60, 100%, 100%)"), Ok((255, 0, 255)));
+        assert_eq!(parse("RebeccaPurple"), Ok((102, 51, 153)));
+        assert_eq!(parse("orange"), Ok((255, 165, 0)));
+        assert_eq!(parse("off"), Ok((0, 0, 0)));
+    }
+
+    #[test]
+    fn parse_invalid_colors() {
+        for input in [
+            "",
+            "#ff",
+            "#gggggg",
+            "notacolor",
+            "rgb(256, 0, 0)",
+            "rgb(1, 2)",
+            "hsl(0, 101%, 50%)",
+            "hsv(0 0 0",
+        ] {
+            assert_eq!(
+                input.parse::<Color>(),
+                Err(ParseColorError {
+                    input: input.to_string()
+                }),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn scale_and_blend() {
+        let magenta = Color::new(255, 0, 255);
+        assert_eq!(magenta.scale(0.5), Color::new(128, 0, 128));
+        assert_eq!(magenta.scale(2.0), magenta);
+        assert_eq!(Color::OFF.blend(magenta, 0.25), Color::new(64, 0, 64));
+        assert_eq!(Color::OFF.blend(magenta, 1.0), magenta);
+    }
+
+    #[test]
+    fn interpolations() {
+        let red = Color::new(255, 0, 0);
+        let blue = Color::new(0, 0, 255);
+        assert_eq!(
+            red.interpolate(blue, 0.5, Interpolation::Linear),
+            Color::new(128, 0, 128)
+        );
+        assert_eq!(
+            red.interpolate(blue, 0.5, Interpolation::Hsl),
+            Color::new(255, 0, 255)
+        );
+        // Gray takes the hue of the other color.
+        assert_eq!(
+            Color::new(128, 128, 128).interpolate(red, 0.0, Interpolation::Hsl),
+            Color::new(128, 128, 128)
+        );
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::Hsl,
+            Interpolation::Oklab,
+        ] {
+            assert_eq!(red.interpolate(blue, 0.0, interpolation), red);
+            assert_eq!(red.interpolate(blue, 1.0, interpolation), blue);
+        }
+        // OKLab keeps the middle of black and white brighter than the average of the channels.
+        let middle = Color::OFF.interpolate(Color::new(255, 255, 255), 0.5, Interpolation::Oklab);
+        assert_eq!(middle, Color::new(99, 99, 99));
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for color in [
+            Color::new(255, 136, 0),
+            Color::new(102, 51, 153),
+            Color::new(10, 20, 30),
+        ] {
+            let (h, s, l) = color.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l), color);
+        }
+    }
+
+    #[test]
+    fn gradient_stops() {
+        let red = Color::new(255, 0, 0);
+        let blue = Color::new(0, 0, 255);
+        let gradient = Gradient::with_stops([(1.0, blue), (0.0, red), (0.5, red)])
+            .interpolation(Interpolation::Linear);
+        assert_eq!(gradient.at(0.25), red);
+        assert_eq!(gradient.at(0.75), Color::new(128, 0, 128));
+        assert_eq!(gradient.at(2.0), blue);
+        assert_eq!(gradient.colors(3), vec![red, red, blue]);
+        assert_eq!(Gradient::new([red]).colors(2), vec![red, red]);
+        assert_eq!(Gradient::default().at(0.5), Color::OFF);
+    }
+
+    #[test]
+    fn gamma_correction() {
+        assert_eq!(Color::new(255, 128, 0).gamma(1.0), Color::new(255, 128, 0));
+        assert_eq!(
+            Color::new(255, 128, 0).gamma_corrected(),
+            Color::new(255, 37, 0)
+        );
+    }
+}
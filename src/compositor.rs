@@ -0,0 +1,459 @@
+//! Compositing the LED colors set by several clients sharing a keyboard.
+//!
+//! Each client draws in its own named layer with a priority, an LED shows the color of the
+//! highest priority layer setting it. When a layer is released, the colors of the layers below
+//! it show again. The daemon composites the calls of its clients, which choose their layer with
+//! [`KontrollBuilder::led_layer`](crate::KontrollBuilder::led_layer):
+//!
+//! ```rust
+//! use kontroll::compositor::{Compositor, LedUpdate};
+//!
+//! let mut compositor = Compositor::new();
+//! compositor.set_all("clock", 0, (0, 0, 255), None);
+//! compositor.set_led("ci-status", 10, 12, (255, 0, 0), None);
+//! assert_eq!(compositor.color(12), Some((255, 0, 0)));
+//!
+//! // The clock's color shows again once the CI status is released.
+//! assert_eq!(
+//!     compositor.release("ci-status"),
+//!     vec![LedUpdate::Led(12, (0, 0, 255), None)]
+//! );
+//! ```
+//!
+//! Colors can be sustained, as with Keymapp: they last for the given time, then the layers
+//! below show again. Colors redrawn after a change keep the sustain they have left.
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+/// The request header naming the LED layer a client draws in.
+pub const LAYER_HEADER: &str = "x-kontroll-layer";
+/// The request header holding the priority of the client's LED layer, higher is on top.
+pub const PRIORITY_HEADER: &str = "x-kontroll-priority";
+/// The request header marking a `SetRgbAll` call as restoring the LEDs, which releases the
+/// client's LED layer.
+pub const RESTORE_HEADER: &str = "x-kontroll-restore";
+/// The layer of clients that don't name one, with priority 0.
+pub const DEFAULT_LAYER: &str = "default";
+
+type Rgb = (u8, u8, u8);
+
+/// A call to make to the keyboard so its LEDs show the composited colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedUpdate {
+    /// Sets an LED to a color, for the given time or until it is changed.
+    Led(usize, Rgb, Option<Duration>),
+    /// Sets all LEDs to a color, for the given time or until they are changed.
+    All(Rgb, Option<Duration>),
+    /// Restores all LEDs to their default color.
+    Restore,
+}
+
+// A color, lasting until the given time or until it is changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lit {
+    color: Rgb,
+    until: Option<Instant>,
+}
+
+impl Lit {
+    fn new(now: Instant, color: Rgb, sustain: Option<Duration>) -> Self {
+        Self {
+            color,
+            until: sustain.map(|sustain| now + sustain),
+        }
+    }
+
+    fn live(self, now: Instant) -> Option<Self> {
+        self.until.is_none_or(|until| until > now).then_some(self)
+    }
+
+    fn sustain(self, now: Instant) -> Option<Duration> {
+        self.until.map(|until| until.saturating_duration_since(now))
+    }
+}
+
+struct Layer {
+    name: String,
+    priority: i32,
+    all: Option<Lit>,
+    leds: BTreeMap<usize, Lit>,
+}
+
+impl Layer {
+    fn lit(&self, now: Instant, led: usize) -> Option<Lit> {
+        match self.leds.get(&led).and_then(|lit| lit.live(now)) {
+            Some(lit) => Some(lit),
+            None => self.all.and_then(|lit| lit.live(now)),
+        }
+    }
+}
+
+// The colors of the keyboard: the LEDs set individually, and the color of every other LED.
+#[derive(Default)]
+struct Composite {
+    rest: Option<Lit>,
+    leds: BTreeMap<usize, Lit>,
+}
+
+impl Composite {
+    // What the keyboard shows, an LED whose color ran out is back to its default color.
+    fn shown(&self, now: Instant, led: usize) -> Option<Lit> {
+        match self.leds.get(&led) {
+            Some(lit) => lit.live(now),
+            None => self.rest.and_then(|lit| lit.live(now)),
+        }
+    }
+
+    fn apply(&mut self, now: Instant, update: LedUpdate) {
+        match update {
+            LedUpdate::Led(led, color, sustain) => {
+                self.leds.insert(led, Lit::new(now, color, sustain));
+            }
+            LedUpdate::All(color, sustain) => {
+                self.leds.clear();
+                self.rest = Some(Lit::new(now, color, sustain));
+            }
+            LedUpdate::Restore => *self = Composite::default(),
+        }
+    }
+}
+
+/// The LED layers of the clients sharing a keyboard.
+///
+/// Every change returns the calls to make so the keyboard shows the new composited colors, an
+/// empty list when the change is hidden by a layer above.
+#[derive(Default)]
+pub struct Compositor {
+    // Sorted by priority, the top layer last.
+    layers: Vec<Layer>,
+    // What was sent to the keyboard.
+    shown: Composite,
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets an LED's color within a layer, claiming the layer if needed. A sustained color
+    /// lasts for the given time.
+    pub fn set_led(
+        &mut self,
+        layer: &str,
+        priority: i32,
+        led: usize,
+        color: Rgb,
+        sustain: Option<Duration>,
+    ) -> Vec<LedUpdate> {
+        self.set_led_at(Instant::now(), layer, priority, led, color, sustain)
+    }
+
+    /// Sets the color of all LEDs within a layer, replacing the LEDs it set before. A sustained
+    /// color lasts for the given time.
+    pub fn set_all(
+        &mut self,
+        layer: &str,
+        priority: i32,
+        color: Rgb,
+        sustain: Option<Duration>,
+    ) -> Vec<LedUpdate> {
+        self.set_all_at(Instant::now(), layer, priority, color, sustain)
+    }
+
+    /// Releases a layer, the colors of the layers below it show again.
+    pub fn release(&mut self, layer: &str) -> Vec<LedUpdate> {
+        self.release_at(Instant::now(), layer)
+    }
+
+    /// Redraws the LEDs whose sustained color ran out, showing the layers below them.
+    pub fn refresh(&mut self) -> Vec<LedUpdate> {
+        self.diff(Instant::now())
+    }
+
+    /// Returns when the next sustained color runs out, when [`Compositor::refresh`] should be
+    /// called.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.next_expiry_at(Instant::now())
+    }
+
+    /// Returns the composited color of an LED, `None` when no layer sets it.
+    pub fn color(&self, led: usize) -> Option<Rgb> {
+        self.lit(Instant::now(), led).map(|lit| lit.color)
+    }
+
+    /// Returns the names and priorities of the claimed layers, the top layer first.
+    pub fn layers(&self) -> Vec<(&str, i32)> {
+        self.layers
+            .iter()
+            .rev()
+            .map(|layer| (layer.name.as_str(), layer.priority))
+            .collect()
+    }
+
+    fn next_expiry_at(&self, now: Instant) -> Option<Instant> {
+        let layers = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.all.iter().chain(layer.leds.values()));
+        let shown = self.shown.rest.iter().chain(self.shown.leds.values());
+        layers
+            .chain(shown)
+            .filter_map(|lit| lit.until)
+            .filter(|&until| until > now)
+            .min()
+    }
+
+    fn set_led_at(
+        &mut self,
+        now: Instant,
+        layer: &str,
+        priority: i32,
+        led: usize,
+        color: Rgb,
+        sustain: Option<Duration>,
+    ) -> Vec<LedUpdate> {
+        self.update(now, layer, priority, |layer| {
+            layer.leds.insert(led, Lit::new(now, color, sustain));
+        })
+    }
+
+    fn set_all_at(
+        &mut self,
+        now: Instant,
+        layer: &str,
+        priority: i32,
+        color: Rgb,
+        sustain: Option<Duration>,
+    ) -> Vec<LedUpdate> {
+        self.update(now, layer, priority, |layer| {
+            layer.leds.clear();
+            layer.all = Some(Lit::new(now, color, sustain));
+        })
+    }
+
+    fn release_at(&mut self, now: Instant, layer: &str) -> Vec<LedUpdate> {
+        self.layers.retain(|l| l.name != layer);
+        self.diff(now)
+    }
+
+    fn lit(&self, now: Instant, led: usize) -> Option<Lit> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.lit(now, led))
+    }
+
+    fn update(
+        &mut self,
+        now: Instant,
+        name: &str,
+        priority: i32,
+        f: impl FnOnce(&mut Layer),
+    ) -> Vec<LedUpdate> {
+        let mut layer = match self.layers.iter().position(|l| l.name == name) {
+            Some(index) => self.layers.remove(index),
+            None => Layer {
+                name: name.to_string(),
+                priority,
+                all: None,
+                leds: BTreeMap::new(),
+            },
+        };
+        layer.priority = priority;
+        f(&mut layer);
+        // The latest layer to change goes on top of the layers with the same priority.
+        let index = self.layers.partition_point(|l| l.priority <= priority);
+        self.layers.insert(index, layer);
+        self.diff(now)
+    }
+
+    // The colors the layers ask for.
+    fn composite(&self, now: Instant) -> Composite {
+        let rest = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.all.and_then(|lit| lit.live(now)));
+        let leds = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.leds.keys())
+            .filter_map(|&led| Some((led, self.lit(now, led)?)))
+            .collect();
+        Composite { rest, leds }
+    }
+
+    // Returns the updates taking the keyboard from what it shows to the composited colors.
+    fn diff(&mut self, now: Instant) -> Vec<LedUpdate> {
+        let wanted = self.composite(now);
+        let shown_rest = self.shown.rest.and_then(|lit| lit.live(now));
+        let leds: BTreeSet<usize> = wanted
+            .leds
+            .keys()
+            .chain(self.shown.leds.keys())
+            .copied()
+            .collect();
+        let wanted_lit = |led: usize| wanted.leds.get(&led).copied().or(wanted.rest);
+        let changed: Vec<(usize, Option<Lit>)> = leds
+            .into_iter()
+            .map(|led| (led, wanted_lit(led)))
+            .filter(|&(led, lit)| self.shown.shown(now, led) != lit)
+            .collect();
+
+        // A single LED can't be restored, restoring them all and setting the others again is
+        // the only way back to its default color.
+        let updates = if wanted.rest != shown_rest || changed.iter().any(|(_, lit)| lit.is_none()) {
+            let mut updates = vec![wanted.rest.map_or(LedUpdate::Restore, |lit| {
+                LedUpdate::All(lit.color, lit.sustain(now))
+            })];
+            updates.extend(
+                wanted
+                    .leds
+                    .iter()
+                    .filter(|(_, &lit)| Some(lit) != wanted.rest)
+                    .map(|(&led, lit)| LedUpdate::Led(led, lit.color, lit.sustain(now))),
+            );
+            updates
+        } else {
+            changed
+                .into_iter()
+                .filter_map(|(led, lit)| Some(LedUpdate::Led(led, lit?.color, lit?.sustain(now))))
+                .collect()
+        };
+        for &update in &updates {
+            self.shown.apply(now, update);
+        }
+        self.forget_expired(now);
+        updates
+    }
+
+    fn forget_expired(&mut self, now: Instant) {
+        for layer in &mut self.layers {
+            layer.all = layer.all.and_then(|lit| lit.live(now));
+            layer.leds.retain(|_, lit| lit.live(now).is_some());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    const RED: Rgb = (255, 0, 0);
+    const GREEN: Rgb = (0, 255, 0);
+    const BLUE: Rgb = (0, 0, 255);
+
+    #[test]
+    fn higher_priority_wins() {
+        let mut compositor = Compositor::new();
+        assert_eq!(
+            compositor.set_led("ci-status", 10, 1, RED, None),
+            vec![LedUpdate::Led(1, RED, None)]
+        );
+        // Hidden by the CI status.
+        assert_eq!(compositor.set_led("clock", 0, 1, GREEN, None), vec![]);
+        assert_eq!(
+            compositor.set_led("clock", 0, 2, GREEN, None),
+            vec![LedUpdate::Led(2, GREEN, None)]
+        );
+        assert_eq!(compositor.color(1), Some(RED));
+        assert_eq!(compositor.layers(), vec![("ci-status", 10), ("clock", 0)]);
+
+        assert_eq!(
+            compositor.release("ci-status"),
+            vec![LedUpdate::Led(1, GREEN, None)]
+        );
+        assert_eq!(compositor.color(1), Some(GREEN));
+    }
+
+    #[test]
+    fn set_all_below_leds() {
+        let mut compositor = Compositor::new();
+        compositor.set_led("ci-status", 10, 1, RED, None);
+        assert_eq!(
+            compositor.set_all("clock", 0, BLUE, None),
+            vec![LedUpdate::All(BLUE, None), LedUpdate::Led(1, RED, None)]
+        );
+        assert_eq!(compositor.color(5), Some(BLUE));
+        assert_eq!(
+            compositor.release("ci-status"),
+            vec![LedUpdate::Led(1, BLUE, None)]
+        );
+    }
+
+    #[test]
+    fn releasing_everything_restores() {
+        let mut compositor = Compositor::new();
+        compositor.set_led("ci-status", 10, 1, RED, None);
+        compositor.set_led("clock", 0, 2, GREEN, None);
+        // LED 1 can only go back to its default color by restoring them all.
+        assert_eq!(
+            compositor.release("ci-status"),
+            vec![LedUpdate::Restore, LedUpdate::Led(2, GREEN, None)]
+        );
+        assert_eq!(compositor.release("clock"), vec![LedUpdate::Restore]);
+        assert_eq!(compositor.release("clock"), vec![]);
+    }
+
+    #[test]
+    fn priority_changes_move_the_layer() {
+        let mut compositor = Compositor::new();
+        compositor.set_led("a", 1, 1, RED, None);
+        compositor.set_led("b", 2, 1, GREEN, None);
+        assert_eq!(
+            compositor.set_led("a", 3, 1, RED, None),
+            vec![LedUpdate::Led(1, RED, None)]
+        );
+        assert_eq!(compositor.layers(), vec![("a", 3), ("b", 2)]);
+    }
+
+    #[test]
+    fn layers_keep_their_own_sustain() {
+        let start = Instant::now();
+        let at = |ms: u32| start + MS * ms;
+        let mut compositor = Compositor::new();
+        compositor.set_all_at(at(0), "clock", 0, BLUE, None);
+        assert_eq!(
+            compositor.set_led_at(at(0), "ci-status", 10, 1, RED, Some(MS * 200)),
+            vec![LedUpdate::Led(1, RED, Some(MS * 200))]
+        );
+        // The clock's permanent color doesn't take the CI status' sustain, the CI status keeps
+        // what it has left.
+        assert_eq!(
+            compositor.set_all_at(at(50), "clock", 0, GREEN, None),
+            vec![
+                LedUpdate::All(GREEN, None),
+                LedUpdate::Led(1, RED, Some(MS * 150))
+            ]
+        );
+        assert_eq!(compositor.next_expiry_at(at(50)), Some(at(200)));
+        // Once the CI status runs out, the keyboard shows LED 1's default color until it's
+        // redrawn with the clock's.
+        assert_eq!(
+            compositor.diff(at(200)),
+            vec![LedUpdate::Led(1, GREEN, None)]
+        );
+        assert_eq!(compositor.diff(at(300)), vec![]);
+    }
+
+    #[test]
+    fn released_layers_redraw_sustained_colors() {
+        let start = Instant::now();
+        let at = |ms: u32| start + MS * ms;
+        let mut compositor = Compositor::new();
+        compositor.set_led_at(at(0), "clock", 0, 2, GREEN, Some(MS * 300));
+        assert_eq!(
+            compositor.set_all_at(at(100), "ci-status", 10, RED, None),
+            vec![LedUpdate::All(RED, None)]
+        );
+        // The clock's temporary color stays temporary.
+        assert_eq!(
+            compositor.release_at(at(200), "ci-status"),
+            vec![LedUpdate::Restore, LedUpdate::Led(2, GREEN, Some(MS * 100))]
+        );
+        assert_eq!(compositor.diff(at(300)), vec![]);
+        assert_eq!(compositor.next_expiry_at(at(300)), None);
+    }
+}
@@ -0,0 +1,600 @@
+//! A long-running daemon holding the connection to Keymapp and sharing it between clients.
+//!
+//! The daemon serves the same `KeyboardService` as Keymapp, so any Keymapp client, Kontroll
+//! included, can connect to its socket instead of Keymapp's. Calls failing because Keymapp
+//! can't be reached are retried after reconnecting, clients don't notice Keymapp restarting.
+//!
+//! The LEDs are composited: each client draws in its own LED layer, named by the headers of its
+//! requests, and restoring the LEDs, marked by another header, releases the client's layer so
+//! the colors of the layers below show again. See [`compositor`](crate::compositor).
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UnixListener;
+use tokio::sync::{Mutex, Notify};
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use crate::api::keymapp::{
+    keyboard_service_server::{KeyboardService, KeyboardServiceServer},
+    BrightnessUpdateReply, ConnectAnyKeyboardRequest, ConnectKeyboardReply, ConnectKeyboardRequest,
+    DecreaseBrightnessRequest, DisconnectKeyboardReply, DisconnectKeyboardRequest,
+    GetKeyboardsReply, GetKeyboardsRequest, GetStatusReply, GetStatusRequest,
+    IncreaseBrightnessRequest, SetLayerReply, SetLayerRequest, SetRgbAllReply, SetRgbAllRequest,
+    SetRgbLedReply, SetRgbLedRequest, SetStatusLedReply, SetStatusLedRequest,
+};
+use crate::api::ApiError;
+use crate::backend::KeyboardBackend;
+use crate::compositor::{
+    Compositor, LedUpdate, DEFAULT_LAYER, LAYER_HEADER, PRIORITY_HEADER, RESTORE_HEADER,
+};
+use crate::retry::{self, RetryPolicy};
+use crate::server;
+
+/// Returns the socket the daemon listens on by default, in the user's runtime directory when
+/// there is one.
+pub fn default_socket() -> PathBuf {
+    directories::BaseDirs::new()
+        .and_then(|dirs| dirs.runtime_dir().map(|dir| dir.to_path_buf()))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("kontroll.sock")
+}
+
+/// Shares a backend, usually a [`GrpcBackend`](crate::backend::GrpcBackend) connected to
+/// Keymapp, between all the clients of its socket.
+pub struct Daemon<B> {
+    backend: B,
+    retry_policy: RetryPolicy,
+    // Held while the updates of a change are sent, so they aren't interleaved with another's.
+    compositor: Mutex<Compositor>,
+    // Wakes the redrawing of expired colors when a change may have moved the next expiry.
+    composited: Notify,
+}
+
+impl<B: KeyboardBackend + 'static> Daemon<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            retry_policy: RetryPolicy::default(),
+            compositor: Mutex::new(Compositor::new()),
+            composited: Notify::new(),
+        }
+    }
+
+    /// Sets how calls failing because the backend can't be reached are retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Serves the clients connecting to a bound Unix socket, until the future is dropped.
+    pub async fn serve(self, listener: UnixListener) -> io::Result<()> {
+        let daemon = Arc::new(self);
+        let service = KeyboardServiceServer::new(Service(daemon.clone()));
+        tokio::select! {
+            result = server::serve_service(service, listener, std::future::pending()) => result,
+            never = daemon.redraw_expired() => match never {},
+        }
+    }
+
+    /// Redraws the LEDs when a sustained color runs out, so the layers below it show again
+    /// instead of the default color.
+    async fn redraw_expired(&self) -> Infallible {
+        loop {
+            let next = self.compositor.lock().await.next_expiry();
+            match next {
+                Some(expiry) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(expiry.into()) => {
+                            // A failure is retried by the next change or expiry.
+                            let _ = self.composite(Compositor::refresh).await;
+                        }
+                        _ = self.composited.notified() => {}
+                    }
+                }
+                None => self.composited.notified().await,
+            }
+        }
+    }
+
+    /// Performs a call on the backend, reconnecting and retrying it according to the retry
+    /// policy when the backend can't be reached.
+    async fn call<'a, T, F, Fut>(&'a self, f: F) -> Result<T, Status>
+    where
+        F: Fn(&'a B) -> Fut + Send,
+        Fut: Future<Output = Result<T, Status>> + Send + 'a,
+    {
+        self.retry(f, retry::is_transport_status).await
+    }
+
+    /// Like [`Daemon::call`], for calls that mustn't be applied twice: they are only retried
+    /// when they didn't reach the backend.
+    async fn call_once<'a, T, F, Fut>(&'a self, f: F) -> Result<T, Status>
+    where
+        F: Fn(&'a B) -> Fut + Send,
+        Fut: Future<Output = Result<T, Status>> + Send + 'a,
+    {
+        self.retry(f, |status| retry::is_unsent_code(status.code()))
+            .await
+    }
+
+    async fn retry<'a, T, F, Fut>(&'a self, f: F, retried: fn(&Status) -> bool) -> Result<T, Status>
+    where
+        F: Fn(&'a B) -> Fut + Send,
+        Fut: Future<Output = Result<T, Status>> + Send + 'a,
+    {
+        // A failed reconnection is not fatal, the next attempt will try again.
+        let reconnect = || async {
+            let _ = self.backend.reconnect().await;
+        };
+        self.retry_policy
+            .run(|| f(&self.backend), retried, reconnect)
+            .await
+    }
+
+    /// Applies a change to the LED layers, sending the updates it leads to to the backend.
+    async fn composite<F>(&self, f: F) -> Result<bool, Status>
+    where
+        F: FnOnce(&mut Compositor) -> Vec<LedUpdate>,
+    {
+        let mut compositor = self.compositor.lock().await;
+        let updates = f(&mut compositor);
+        self.composited.notify_one();
+        let mut success = true;
+        for update in updates {
+            success &= match update {
+                LedUpdate::Led(led, (r, g, b), sustain) => {
+                    let request = SetRgbLedRequest {
+                        led: led as i32,
+                        red: r as i32,
+                        green: g as i32,
+                        blue: b as i32,
+                        sustain: sustain_ms(sustain),
+                    };
+                    self.call(|backend| backend.set_rgb_led(request.clone()))
+                        .await?
+                        .success
+                }
+                LedUpdate::All((r, g, b), sustain) => {
+                    let request = SetRgbAllRequest {
+                        red: r as i32,
+                        green: g as i32,
+                        blue: b as i32,
+                        sustain: sustain_ms(sustain),
+                    };
+                    self.call(|backend| backend.set_rgb_all(request.clone()))
+                        .await?
+                        .success
+                }
+                LedUpdate::Restore => {
+                    self.call(|backend| backend.restore_rgb_leds())
+                        .await?
+                        .success
+                }
+            };
+        }
+        Ok(success)
+    }
+
+    async fn set_rgb_led_in(
+        &self,
+        layer: &Layer,
+        request: SetRgbLedRequest,
+    ) -> Result<SetRgbLedReply, Status> {
+        let led = usize::try_from(request.led)
+            .map_err(|_| Status::invalid_argument("LED indexes can't be negative"))?;
+        let color = rgb(request.red, request.green, request.blue)?;
+        let sustain = sustain(request.sustain);
+        let success = self
+            .composite(|compositor| {
+                compositor.set_led(&layer.name, layer.priority, led, color, sustain)
+            })
+            .await?;
+        Ok(SetRgbLedReply { success })
+    }
+
+    async fn set_rgb_all_in(
+        &self,
+        layer: &Layer,
+        request: SetRgbAllRequest,
+    ) -> Result<SetRgbAllReply, Status> {
+        let color = rgb(request.red, request.green, request.blue)?;
+        let sustain = sustain(request.sustain);
+        let success = self
+            .composite(|compositor| compositor.set_all(&layer.name, layer.priority, color, sustain))
+            .await?;
+        Ok(SetRgbAllReply { success })
+    }
+
+    async fn restore_rgb_leds_in(&self, layer: &Layer) -> Result<SetRgbAllReply, Status> {
+        let success = self
+            .composite(|compositor| compositor.release(&layer.name))
+            .await?;
+        Ok(SetRgbAllReply { success })
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn rgb(red: i32, green: i32, blue: i32) -> Result<(u8, u8, u8), Status> {
+    let channel = |value: i32| {
+        u8::try_from(value).map_err(|_| {
+            Status::invalid_argument(format!("color channels must be 0 to 255, got {}", value))
+        })
+    };
+    Ok((channel(red)?, channel(green)?, channel(blue)?))
+}
+
+// Keymapp keeps colors with a sustain of 0 until they are changed.
+fn sustain(sustain_ms: i32) -> Option<Duration> {
+    u64::try_from(sustain_ms)
+        .ok()
+        .filter(|&ms| ms > 0)
+        .map(Duration::from_millis)
+}
+
+// Rounded up, a sustain of 0 would keep the color.
+fn sustain_ms(sustain: Option<Duration>) -> i32 {
+    sustain.map_or(0, |sustain| {
+        let ms = sustain.as_nanos().div_ceil(1_000_000);
+        ms.clamp(1, i32::MAX as u128) as i32
+    })
+}
+
+/// The LED layer a client draws in.
+struct Layer {
+    name: String,
+    priority: i32,
+}
+
+impl Layer {
+    // Clients that don't name a layer share the default one.
+    #[allow(clippy::result_large_err)]
+    fn from_metadata(metadata: &MetadataMap) -> Result<Self, Status> {
+        let name = match metadata.get(LAYER_HEADER) {
+            Some(name) => name
+                .to_str()
+                .map_err(|_| Status::invalid_argument("invalid LED layer name"))?,
+            None => DEFAULT_LAYER,
+        };
+        let priority = match metadata.get(PRIORITY_HEADER) {
+            Some(priority) => priority
+                .to_str()
+                .ok()
+                .and_then(|priority| priority.parse().ok())
+                .ok_or_else(|| Status::invalid_argument("invalid LED layer priority"))?,
+            None => 0,
+        };
+        Ok(Self {
+            name: name.to_string(),
+            priority,
+        })
+    }
+}
+
+fn default_layer() -> Layer {
+    Layer {
+        name: DEFAULT_LAYER.to_string(),
+        priority: 0,
+    }
+}
+
+#[tonic::async_trait]
+impl<B: KeyboardBackend + 'static> KeyboardBackend for Daemon<B> {
+    async fn get_status(&self) -> Result<GetStatusReply, Status> {
+        self.call(|backend| backend.get_status()).await
+    }
+
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status> {
+        self.call(|backend| backend.get_keyboards()).await
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status> {
+        self.call(|backend| backend.connect_keyboard(request.clone()))
+            .await
+    }
+
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status> {
+        self.call(|backend| backend.connect_any_keyboard()).await
+    }
+
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status> {
+        self.call(|backend| backend.disconnect_keyboard()).await
+    }
+
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        self.call(|backend| backend.set_layer(request.clone()))
+            .await
+    }
+
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        self.call(|backend| backend.unset_layer(request.clone()))
+            .await
+    }
+
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status> {
+        self.set_rgb_led_in(&default_layer(), request).await
+    }
+
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status> {
+        self.set_rgb_all_in(&default_layer(), request).await
+    }
+
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status> {
+        self.call(|backend| backend.set_status_led(request.clone()))
+            .await
+    }
+
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        self.call_once(|backend| backend.increase_brightness())
+            .await
+    }
+
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        self.call_once(|backend| backend.decrease_brightness())
+            .await
+    }
+
+    async fn restore_rgb_leds(&self) -> Result<SetRgbAllReply, Status> {
+        self.restore_rgb_leds_in(&default_layer()).await
+    }
+
+    async fn reconnect(&self) -> Result<(), ApiError> {
+        self.backend.reconnect().await
+    }
+}
+
+/// Serves the daemon, reading the LED layer of each client from its requests.
+struct Service<B>(Arc<Daemon<B>>);
+
+#[tonic::async_trait]
+impl<B: KeyboardBackend + 'static> KeyboardService for Service<B> {
+    async fn get_status(
+        &self,
+        _request: Request<GetStatusRequest>,
+    ) -> Result<Response<GetStatusReply>, Status> {
+        self.0.get_status().await.map(Response::new)
+    }
+
+    async fn get_keyboards(
+        &self,
+        _request: Request<GetKeyboardsRequest>,
+    ) -> Result<Response<GetKeyboardsReply>, Status> {
+        self.0.get_keyboards().await.map(Response::new)
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: Request<ConnectKeyboardRequest>,
+    ) -> Result<Response<ConnectKeyboardReply>, Status> {
+        let request = request.into_inner();
+        self.0.connect_keyboard(request).await.map(Response::new)
+    }
+
+    async fn connect_any_keyboard(
+        &self,
+        _request: Request<ConnectAnyKeyboardRequest>,
+    ) -> Result<Response<ConnectKeyboardReply>, Status> {
+        self.0.connect_any_keyboard().await.map(Response::new)
+    }
+
+    async fn disconnect_keyboard(
+        &self,
+        _request: Request<DisconnectKeyboardRequest>,
+    ) -> Result<Response<DisconnectKeyboardReply>, Status> {
+        self.0.disconnect_keyboard().await.map(Response::new)
+    }
+
+    async fn set_layer(
+        &self,
+        request: Request<SetLayerRequest>,
+    ) -> Result<Response<SetLayerReply>, Status> {
+        let request = request.into_inner();
+        self.0.set_layer(request).await.map(Response::new)
+    }
+
+    async fn unset_layer(
+        &self,
+        request: Request<SetLayerRequest>,
+    ) -> Result<Response<SetLayerReply>, Status> {
+        let request = request.into_inner();
+        self.0.unset_layer(request).await.map(Response::new)
+    }
+
+    async fn set_rgb_led(
+        &self,
+        request: Request<SetRgbLedRequest>,
+    ) -> Result<Response<SetRgbLedReply>, Status> {
+        let layer = Layer::from_metadata(request.metadata())?;
+        let request = request.into_inner();
+        self.0
+            .set_rgb_led_in(&layer, request)
+            .await
+            .map(Response::new)
+    }
+
+    async fn set_rgb_all(
+        &self,
+        request: Request<SetRgbAllRequest>,
+    ) -> Result<Response<SetRgbAllReply>, Status> {
+        let layer = Layer::from_metadata(request.metadata())?;
+        if request.metadata().contains_key(RESTORE_HEADER) {
+            return self.0.restore_rgb_leds_in(&layer).await.map(Response::new);
+        }
+        let request = request.into_inner();
+        self.0
+            .set_rgb_all_in(&layer, request)
+            .await
+            .map(Response::new)
+    }
+
+    async fn set_status_led(
+        &self,
+        request: Request<SetStatusLedRequest>,
+    ) -> Result<Response<SetStatusLedReply>, Status> {
+        let request = request.into_inner();
+        self.0.set_status_led(request).await.map(Response::new)
+    }
+
+    async fn increase_brightness(
+        &self,
+        _request: Request<IncreaseBrightnessRequest>,
+    ) -> Result<Response<BrightnessUpdateReply>, Status> {
+        self.0.increase_brightness().await.map(Response::new)
+    }
+
+    async fn decrease_brightness(
+        &self,
+        _request: Request<DecreaseBrightnessRequest>,
+    ) -> Result<Response<BrightnessUpdateReply>, Status> {
+        self.0.decrease_brightness().await.map(Response::new)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tonic::Code;
+
+    use super::*;
+    use crate::backend::{Call, RecordingBackend};
+    use crate::sim::Simulator;
+    use crate::Kontroll;
+
+    #[tokio::test]
+    async fn retries_lost_calls() {
+        let sim = Simulator::new();
+        sim.set_connected_index(0);
+        let daemon = Daemon::new(sim.clone()).retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        });
+        let api = Kontroll::builder()
+            .backend(daemon)
+            .retry_policy(RetryPolicy::none())
+            .connect()
+            .await
+            .unwrap();
+
+        sim.fail_next_request(true);
+        api.set_layer(2).await.unwrap();
+        assert_eq!(sim.current_layer(), 2);
+    }
+
+    #[tokio::test]
+    async fn restores_only_when_marked() {
+        let daemon = Arc::new(Daemon::new(RecordingBackend::new()));
+        let api = Kontroll::with_backend(daemon.clone());
+
+        // Black for 1ms is what Keymapp restores from, the daemon paints it like any color.
+        api.set_rgb_all(0, 0, 0, 1).await.unwrap();
+        assert_eq!(
+            daemon.compositor.lock().await.layers(),
+            vec![(DEFAULT_LAYER, 0)]
+        );
+        api.restore_rgb_leds().await.unwrap();
+        assert!(daemon.compositor.lock().await.layers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn redraws_layers_with_their_own_sustain() {
+        let backend = RecordingBackend::new();
+        let daemon = Daemon::new(backend.clone());
+        let base = Layer {
+            name: "base".to_string(),
+            priority: 0,
+        };
+        let alert = Layer {
+            name: "alert".to_string(),
+            priority: 10,
+        };
+        let led = |led, sustain| SetRgbLedRequest {
+            led,
+            red: 255,
+            green: 0,
+            blue: 0,
+            sustain,
+        };
+
+        daemon.set_rgb_led_in(&base, led(3, 0)).await.unwrap();
+        daemon.set_rgb_led_in(&base, led(4, 60_000)).await.unwrap();
+        let all = SetRgbAllRequest {
+            red: 0,
+            green: 0,
+            blue: 255,
+            sustain: 200,
+        };
+        daemon.set_rgb_all_in(&alert, all.clone()).await.unwrap();
+        assert_eq!(backend.calls().last(), Some(&Call::SetRgbAll(all)));
+
+        // Releasing the alert redraws the LEDs of the base layer with their own sustain.
+        daemon.restore_rgb_leds_in(&alert).await.unwrap();
+        let calls = backend.calls();
+        assert_eq!(calls[calls.len() - 2], Call::SetRgbLed(led(3, 0)));
+        match &calls[calls.len() - 1] {
+            Call::SetRgbLed(request) => {
+                assert_eq!(request.led, 4);
+                assert!((1..=60_000).contains(&request.sustain));
+            }
+            call => panic!("unexpected call {call:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_colors_out_of_range() {
+        let backend = RecordingBackend::new();
+        let daemon = Daemon::new(backend.clone());
+        let layer = Layer {
+            name: DEFAULT_LAYER.to_string(),
+            priority: 0,
+        };
+
+        let request = SetRgbAllRequest {
+            red: 256,
+            green: 0,
+            blue: 0,
+            sustain: 0,
+        };
+        let status = daemon.set_rgb_all_in(&layer, request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forwards_sustain() {
+        let backend = RecordingBackend::new();
+        let api = Kontroll::with_backend(Daemon::new(backend.clone()));
+
+        api.set_rgb_led(3, 255, 0, 0, 500).await.unwrap();
+        api.set_rgb_all(0, 0, 255, 200).await.unwrap();
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::SetRgbLed(SetRgbLedRequest {
+                    led: 3,
+                    red: 255,
+                    green: 0,
+                    blue: 0,
+                    sustain: 500,
+                }),
+                Call::SetRgbAll(SetRgbAllRequest {
+                    red: 0,
+                    green: 0,
+                    blue: 255,
+                    sustain: 200,
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Runs commands when the keyboard enters or leaves a layer, so the keyboard can drive the
+//! desktop.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio_stream::StreamExt;
+
+use kontroll::{Kontroll, StatusEvent};
+
+use crate::cli::CliError;
+
+/// The commands run when entering and leaving a layer.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LayerHooks {
+    pub on_enter: Option<String>,
+    pub on_leave: Option<String>,
+}
+
+/// Kontroll's config file.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// The hooks of each layer, by index.
+    #[serde(default)]
+    pub layers: BTreeMap<i32, LayerHooks>,
+}
+
+impl Config {
+    /// Returns the default location of the config file.
+    pub fn default_path() -> Option<PathBuf> {
+        #[cfg(not(target_os = "windows"))]
+        return directories::BaseDirs::new()
+            .map(|dirs| dirs.config_dir().join("kontroll").join("config.json"));
+        #[cfg(target_os = "windows")]
+        None
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CliError> {
+        let content = std::fs::read_to_string(path).map_err(CliError::Io)?;
+        serde_json::from_str(&content)
+            .map_err(|e| CliError::Usage(format!("{}: {}", path.display(), e)))
+    }
+}
+
+/// Returns the commands to run when going from the `previous` layer to `layer`, `None` when
+/// no keyboard is connected: the previous layer's `on-leave` then the new layer's `on-enter`.
+fn commands(
+    hooks: &BTreeMap<i32, LayerHooks>,
+    previous: Option<i32>,
+    layer: Option<i32>,
+) -> Vec<&str> {
+    if previous == layer {
+        return Vec::new();
+    }
+    let leave = previous
+        .and_then(|previous| hooks.get(&previous))
+        .and_then(|hooks| hooks.on_leave.as_deref());
+    let enter = layer
+        .and_then(|layer| hooks.get(&layer))
+        .and_then(|hooks| hooks.on_enter.as_deref());
+    leave.into_iter().chain(enter).collect()
+}
+
+fn shell(command: &str) -> tokio::process::Command {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = tokio::process::Command::new(program);
+    shell.args([flag, command]);
+    shell
+}
+
+/// Runs the commands one after the other in the background, with the layers passed in
+/// `KONTROLL_LAYER` and `KONTROLL_PREV_LAYER`, empty when there is none.
+fn spawn(commands: Vec<String>, previous: Option<i32>, layer: Option<i32>) {
+    let env = |layer: Option<i32>| layer.map(|layer| layer.to_string()).unwrap_or_default();
+    let (previous, layer) = (env(previous), env(layer));
+    tokio::spawn(async move {
+        for command in commands {
+            let status = shell(&command)
+                .env("KONTROLL_LAYER", &layer)
+                .env("KONTROLL_PREV_LAYER", &previous)
+                .status()
+                .await;
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(status) => eprintln!("{}: {}", command, status),
+                Err(e) => eprintln!("{}: {}", command, e),
+            }
+        }
+    });
+}
+
+/// Watches the layer of the keyboard and runs the hooks of the layers it enters and leaves,
+/// forever. The layer active when watching starts is entered.
+pub async fn run(api: &Kontroll, hooks: &BTreeMap<i32, LayerHooks>, interval: Duration) {
+    let events = api.watch_status(interval);
+    tokio::pin!(events);
+    let mut current = None;
+    while let Some(event) = events.next().await {
+        let layer = match event {
+            StatusEvent::LayerChanged { layer, .. }
+            | StatusEvent::KeyboardConnected { layer, .. } => Some(layer),
+            StatusEvent::KeyboardDisconnected { .. } => None,
+            StatusEvent::KeymappVersionChanged { .. } => continue,
+        };
+        let commands = commands(hooks, current, layer)
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if !commands.is_empty() {
+            spawn(commands, current, layer);
+        }
+        current = layer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        let config: Config = serde_json::from_str(
+            r#"{"layers": {"2": {"on-enter": "notify-send gaming", "on-leave": "notify-send work"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.layers[&2],
+            LayerHooks {
+                on_enter: Some("notify-send gaming".to_string()),
+                on_leave: Some("notify-send work".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn leave_then_enter() {
+        let hooks = BTreeMap::from([
+            (
+                1,
+                LayerHooks {
+                    on_enter: Some("enter 1".to_string()),
+                    on_leave: Some("leave 1".to_string()),
+                },
+            ),
+            (
+                2,
+                LayerHooks {
+                    on_enter: Some("enter 2".to_string()),
+                    on_leave: None,
+                },
+            ),
+        ]);
+        assert_eq!(commands(&hooks, None, Some(1)), vec!["enter 1"]);
+        assert_eq!(
+            commands(&hooks, Some(1), Some(2)),
+            vec!["leave 1", "enter 2"]
+        );
+        assert_eq!(commands(&hooks, Some(2), Some(0)), Vec::<&str>::new());
+        assert_eq!(commands(&hooks, Some(1), None), vec!["leave 1"]);
+        assert_eq!(commands(&hooks, Some(1), Some(1)), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,575 @@
+//! The geometry of the keyboards Kontroll can drive: how many LEDs they have, where each LED
+//! sits and which part of the keyboard it belongs to.
+//!
+//! The model of the connected keyboard is found from the name Keymapp reports:
+//!
+//! ```rust
+//! use kontroll::keyboard::{self, Half};
+//!
+//! let voyager = keyboard::from_friendly_name("Voyager").unwrap();
+//! assert_eq!(voyager.led_count(), 52);
+//! assert_eq!(voyager.led_at(4, 4), Some(24));
+//! assert_eq!(voyager.thumbs(Some(Half::Left)), vec![24, 25]);
+//! ```
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// A half of a split keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Half {
+    Left,
+    Right,
+}
+
+/// An RGB LED, under a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Led {
+    /// The index to pass to [`Kontroll::set_rgb_led`](crate::Kontroll::set_rgb_led).
+    pub index: usize,
+    /// The row of the key, from the top, the thumb clusters being the last rows.
+    pub row: usize,
+    /// The column of the key, from the left of the left half.
+    pub col: usize,
+    /// The horizontal position of the center of the key, in key widths from the left edge.
+    pub x: f32,
+    /// The vertical position of the center of the key, in key widths from the top edge,
+    /// following the stagger of the columns.
+    pub y: f32,
+    pub half: Half,
+    /// Whether the key is in a thumb cluster.
+    pub thumb: bool,
+}
+
+// A hole in the grid of LEDs.
+const __: u8 = u8::MAX;
+
+/// A keyboard model, with the layout of its RGB LEDs.
+///
+/// The LEDs are laid out on a grid of rows and columns spanning both halves, left to right,
+/// the thumb clusters on the last rows. Positions are approximate, good enough to draw the
+/// keyboard or to light the keys in a region, not to machine a case.
+#[derive(Debug, PartialEq)]
+pub struct KeyboardModel {
+    name: &'static str,
+    friendly_name: &'static str,
+    status_leds: usize,
+    // The LED index of each key, `__` where there is no key.
+    grid: &'static [&'static [u8]],
+    // The vertical offset of each column, in key widths.
+    stagger: &'static [f32],
+    // The first row of the thumb clusters, and the LEDs of their keys on the rows above.
+    thumb_row: usize,
+    thumb_keys: &'static [u8],
+    // The gap between the halves, in key widths.
+    gap: f32,
+}
+
+pub static VOYAGER: KeyboardModel = KeyboardModel {
+    name: "voyager",
+    friendly_name: "Voyager",
+    status_leds: 4,
+    #[rustfmt::skip]
+    grid: &[
+        &[ 0,  1,  2,  3,  4,  5,   26, 27, 28, 29, 30, 31],
+        &[ 6,  7,  8,  9, 10, 11,   32, 33, 34, 35, 36, 37],
+        &[12, 13, 14, 15, 16, 17,   38, 39, 40, 41, 42, 43],
+        &[18, 19, 20, 21, 22, 23,   44, 45, 46, 47, 48, 49],
+        &[__, __, __, __, 24, 25,   50, 51, __, __, __, __],
+    ],
+    stagger: &[
+        0.5, 0.5, 0.25, 0.0, 0.25, 0.375, 0.375, 0.25, 0.0, 0.25, 0.5, 0.5,
+    ],
+    thumb_row: 4,
+    thumb_keys: &[],
+    gap: 3.0,
+};
+
+// The LEDs of each half go down the columns, from the outer column in, then the thumb cluster
+// from the big red key.
+pub static MOONLANDER: KeyboardModel = KeyboardModel {
+    name: "moonlander",
+    friendly_name: "Moonlander",
+    status_leds: 6,
+    #[rustfmt::skip]
+    grid: &[
+        &[ 0,  5, 10, 15, 20, 25, 29,   65, 61, 56, 51, 46, 41, 36],
+        &[ 1,  6, 11, 16, 21, 26, 30,   66, 62, 57, 52, 47, 42, 37],
+        &[ 2,  7, 12, 17, 22, 27, 31,   67, 63, 58, 53, 48, 43, 38],
+        &[ 3,  8, 13, 18, 23, 28, __,   __, 64, 59, 54, 49, 44, 39],
+        &[ 4,  9, 14, 19, 24, __, 32,   68, __, 60, 55, 50, 45, 40],
+        &[__, __, __, __, 33, 34, 35,   71, 70, 69, __, __, __, __],
+    ],
+    stagger: &[
+        0.5, 0.5, 0.25, 0.0, 0.25, 0.375, 0.5, 0.5, 0.375, 0.25, 0.0, 0.25, 0.5, 0.5,
+    ],
+    thumb_row: 5,
+    thumb_keys: &[32, 68],
+    gap: 2.0,
+};
+
+// Keymapp can't drive the RGB LEDs of the ErgoDox EZ.
+pub static ERGODOX_EZ: KeyboardModel = KeyboardModel {
+    name: "ergodox",
+    friendly_name: "ErgoDox EZ",
+    status_leds: 3,
+    grid: &[],
+    stagger: &[],
+    thumb_row: 0,
+    thumb_keys: &[],
+    gap: 0.0,
+};
+
+/// Every known keyboard model.
+pub static MODELS: &[&KeyboardModel] = &[&VOYAGER, &MOONLANDER, &ERGODOX_EZ];
+
+/// Returns the model of a keyboard from the name reported by Keymapp, such as `Voyager` or
+/// `Moonlander Mark I`.
+pub fn from_friendly_name(friendly_name: &str) -> Option<&'static KeyboardModel> {
+    let friendly_name = friendly_name.to_lowercase();
+    MODELS
+        .iter()
+        .find(|model| friendly_name.starts_with(&model.friendly_name.to_lowercase()))
+        .copied()
+}
+
+/// Returns a model from its short name: `voyager`, `moonlander` or `ergodox`, or from its
+/// friendly name with dashes for spaces, such as `ergodox-ez`.
+pub fn from_name(name: &str) -> Option<&'static KeyboardModel> {
+    let name = name.to_lowercase();
+    MODELS
+        .iter()
+        .find(|model| {
+            model.name == name || model.friendly_name.to_lowercase().replace(' ', "-") == name
+        })
+        .copied()
+}
+
+impl KeyboardModel {
+    /// The short name of the model, such as `voyager`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The name Keymapp reports for the keyboard.
+    pub fn friendly_name(&self) -> &'static str {
+        self.friendly_name
+    }
+
+    /// Whether Keymapp can set the colors of the keyboard's LEDs.
+    pub fn has_rgb(&self) -> bool {
+        self.led_count() > 0
+    }
+
+    /// The number of RGB LEDs Keymapp can control.
+    pub fn led_count(&self) -> usize {
+        self.keys().count()
+    }
+
+    /// The number of status LEDs.
+    pub fn status_led_count(&self) -> usize {
+        self.status_leds
+    }
+
+    /// The number of rows of the grid of LEDs, thumb clusters included.
+    pub fn rows(&self) -> usize {
+        self.grid.len()
+    }
+
+    /// The number of columns of the grid of LEDs, both halves included.
+    pub fn cols(&self) -> usize {
+        self.grid.first().map_or(0, |row| row.len())
+    }
+
+    /// Returns the LED of the key at a row and column, `None` for holes.
+    pub fn led_at(&self, row: usize, col: usize) -> Option<usize> {
+        let led = *self.grid.get(row)?.get(col)?;
+        (led != __).then_some(led as usize)
+    }
+
+    /// Returns an LED by index.
+    pub fn led(&self, index: usize) -> Option<Led> {
+        self.keys()
+            .find(|&(_, _, led)| led == index)
+            .map(|(row, col, led)| self.make_led(row, col, led))
+    }
+
+    /// Returns all the LEDs, by index.
+    pub fn leds(&self) -> Vec<Led> {
+        let mut leds: Vec<Led> = self
+            .keys()
+            .map(|(row, col, led)| self.make_led(row, col, led))
+            .collect();
+        leds.sort_by_key(|led| led.index);
+        leds
+    }
+
+    /// Returns the LEDs in the order of the keys in QMK's `LAYOUT` macro for the keyboard, the
+    /// order keymaps list their keycodes in: row by row, left to right.
+    pub fn layout_leds(&self) -> Vec<usize> {
+        self.keys().map(|(_, _, led)| led).collect()
+    }
+
+    /// Returns the LEDs of a half, by index.
+    pub fn half(&self, half: Half) -> Vec<usize> {
+        self.filter(|led| led.half == half)
+    }
+
+    /// Returns the LEDs of the thumb clusters, of one half or both, by index.
+    pub fn thumbs(&self, half: Option<Half>) -> Vec<usize> {
+        self.filter(|led| led.thumb && half.is_none_or(|half| led.half == half))
+    }
+
+    /// Returns the LEDs of a row, left to right.
+    pub fn row(&self, row: usize) -> Vec<usize> {
+        (0..self.cols())
+            .filter_map(|col| self.led_at(row, col))
+            .collect()
+    }
+
+    /// Returns the LEDs of a column, top to bottom.
+    pub fn col(&self, col: usize) -> Vec<usize> {
+        (0..self.rows())
+            .filter_map(|row| self.led_at(row, col))
+            .collect()
+    }
+
+    fn filter(&self, f: impl Fn(&Led) -> bool) -> Vec<usize> {
+        self.leds()
+            .into_iter()
+            .filter(f)
+            .map(|led| led.index)
+            .collect()
+    }
+
+    // The row, column and LED of each key.
+    fn keys(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.grid.iter().enumerate().flat_map(|(row, leds)| {
+            leds.iter()
+                .enumerate()
+                .filter(|(_, &led)| led != __)
+                .map(move |(col, &led)| (row, col, led as usize))
+        })
+    }
+
+    fn make_led(&self, row: usize, col: usize, index: usize) -> Led {
+        let half = if col < self.cols() / 2 {
+            Half::Left
+        } else {
+            Half::Right
+        };
+        let gap = if half == Half::Right { self.gap } else { 0.0 };
+        Led {
+            index,
+            row,
+            col,
+            x: col as f32 + gap + 0.5,
+            y: row as f32 + self.stagger[col] + 0.5,
+            half,
+            thumb: row >= self.thumb_row || self.thumb_keys.contains(&(index as u8)),
+        }
+    }
+}
+
+impl fmt::Display for KeyboardModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.friendly_name)
+    }
+}
+
+/// The highest LED index a selector can reach when the model of the keyboard isn't known.
+pub const MAX_LED: usize = u8::MAX as usize;
+
+/// A group of LEDs, by index or by their place on the keyboard.
+///
+/// | Selector                         | LEDs                                    |
+/// | -------------------------------- | --------------------------------------- |
+/// | `12`                             | The LED 12                              |
+/// | `0-11`, `11-0`                   | A range of LEDs, up or down             |
+/// | `r2c3`                           | The key on row 2 and column 3           |
+/// | `row:1`, `col:0`                 | A row, left to right, or a column       |
+/// | `left`, `right`                  | A half                                  |
+/// | `thumbs`, `left-thumbs`, ...     | The thumb clusters, or one of them      |
+/// | `all`                            | Every LED                               |
+///
+/// Rows and columns count from 0, as in [`KeyboardModel::led_at`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedSelector {
+    Index(usize),
+    Range(usize, usize),
+    Key { row: usize, col: usize },
+    Row(usize),
+    Col(usize),
+    Half(Half),
+    Thumbs(Option<Half>),
+    All,
+}
+
+impl LedSelector {
+    /// Whether the selector needs the layout of the keyboard to find its LEDs.
+    pub fn needs_model(&self) -> bool {
+        !matches!(self, LedSelector::Index(_) | LedSelector::Range(..))
+    }
+
+    /// Returns the selected LEDs, in order. Selectors other than indexes and ranges need the
+    /// model of the keyboard, indexes and ranges must be on its LEDs, or up to [`MAX_LED`]
+    /// without a model.
+    pub fn resolve(&self, model: Option<&KeyboardModel>) -> Result<Vec<usize>, String> {
+        let last = match *self {
+            LedSelector::Index(index) => Some(index),
+            LedSelector::Range(start, end) => Some(start.max(end)),
+            _ => None,
+        };
+        match (last, model) {
+            (Some(last), Some(model)) if last >= model.led_count() => {
+                return Err(format!(
+                    "{} is out of range, the {} has {} LEDs",
+                    self,
+                    model,
+                    model.led_count()
+                ))
+            }
+            (Some(last), None) if last > MAX_LED => {
+                return Err(format!(
+                    "{} is out of range, LEDs go up to {}",
+                    self, MAX_LED
+                ))
+            }
+            _ => {}
+        }
+        match (*self, model) {
+            (LedSelector::Index(index), _) => Ok(vec![index]),
+            (LedSelector::Range(start, end), _) if start <= end => Ok((start..=end).collect()),
+            (LedSelector::Range(start, end), _) => Ok((end..=start).rev().collect()),
+            (_, None) => Err(format!(
+                "{} needs the layout of the connected keyboard, which isn't known",
+                self
+            )),
+            (selector, Some(model)) => {
+                let leds = match selector {
+                    LedSelector::Key { row, col } => model.led_at(row, col).into_iter().collect(),
+                    LedSelector::Row(row) => model.row(row),
+                    LedSelector::Col(col) => model.col(col),
+                    LedSelector::Half(half) => model.half(half),
+                    LedSelector::Thumbs(half) => model.thumbs(half),
+                    _ => (0..model.led_count()).collect(),
+                };
+                if leds.is_empty() {
+                    return Err(format!("{} selects no LED on the {}", self, model));
+                }
+                Ok(leds)
+            }
+        }
+    }
+}
+
+impl fmt::Display for LedSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedSelector::Index(index) => write!(f, "{}", index),
+            LedSelector::Range(start, end) => write!(f, "{}-{}", start, end),
+            LedSelector::Key { row, col } => write!(f, "r{}c{}", row, col),
+            LedSelector::Row(row) => write!(f, "row:{}", row),
+            LedSelector::Col(col) => write!(f, "col:{}", col),
+            LedSelector::Half(Half::Left) => f.write_str("left"),
+            LedSelector::Half(Half::Right) => f.write_str("right"),
+            LedSelector::Thumbs(None) => f.write_str("thumbs"),
+            LedSelector::Thumbs(Some(Half::Left)) => f.write_str("left-thumbs"),
+            LedSelector::Thumbs(Some(Half::Right)) => f.write_str("right-thumbs"),
+            LedSelector::All => f.write_str("all"),
+        }
+    }
+}
+
+impl FromStr for LedSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let selector = s.trim().to_lowercase();
+        let number = |n: &str| n.parse::<usize>().ok();
+        let parsed = match selector.as_str() {
+            "all" => Some(LedSelector::All),
+            "left" => Some(LedSelector::Half(Half::Left)),
+            "right" => Some(LedSelector::Half(Half::Right)),
+            "thumbs" => Some(LedSelector::Thumbs(None)),
+            "left-thumbs" => Some(LedSelector::Thumbs(Some(Half::Left))),
+            "right-thumbs" => Some(LedSelector::Thumbs(Some(Half::Right))),
+            s => {
+                if let Some(row) = s.strip_prefix("row:") {
+                    number(row).map(LedSelector::Row)
+                } else if let Some(col) = s.strip_prefix("col:") {
+                    number(col).map(LedSelector::Col)
+                } else if let Some((row, col)) = s.strip_prefix('r').and_then(|s| s.split_once('c'))
+                {
+                    number(row)
+                        .zip(number(col))
+                        .map(|(row, col)| LedSelector::Key { row, col })
+                } else if let Some((start, end)) = s.split_once('-') {
+                    number(start.trim())
+                        .zip(number(end.trim()))
+                        .map(|(start, end)| LedSelector::Range(start, end))
+                } else {
+                    number(s).map(LedSelector::Index)
+                }
+            }
+        };
+        parsed.ok_or_else(|| {
+            format!(
+                "{} is not a valid LED, use an index, a range such as 0-11, r2c3, row:1, col:0, left, right, thumbs or all",
+                s.trim()
+            )
+        })
+    }
+}
+
+/// Selectors separated by commas, such as `left,r4c6` or `1,3,20-24`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedSelection(Vec<LedSelector>);
+
+impl LedSelection {
+    /// Whether any of the selectors needs the layout of the keyboard.
+    pub fn needs_model(&self) -> bool {
+        self.0.iter().any(LedSelector::needs_model)
+    }
+
+    /// Returns the selected LEDs in the order of the selectors, each LED once.
+    pub fn resolve(&self, model: Option<&KeyboardModel>) -> Result<Vec<usize>, String> {
+        let mut leds = Vec::new();
+        let mut seen = HashSet::new();
+        for selector in &self.0 {
+            leds.extend(
+                selector
+                    .resolve(model)?
+                    .into_iter()
+                    .filter(|&led| seen.insert(led)),
+            );
+        }
+        Ok(leds)
+    }
+}
+
+impl fmt::Display for LedSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let selectors: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        f.write_str(&selectors.join(","))
+    }
+}
+
+impl FromStr for LedSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(LedSelection)
+    }
+}
+
+impl From<LedSelector> for LedSelection {
+    fn from(selector: LedSelector) -> Self {
+        LedSelection(vec![selector])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_led_once() {
+        for model in MODELS {
+            let leds = model.leds();
+            assert_eq!(
+                leds.iter().map(|led| led.index).collect::<Vec<_>>(),
+                (0..model.led_count()).collect::<Vec<_>>(),
+                "{}",
+                model
+            );
+            assert_eq!(model.stagger.len(), model.cols(), "{}", model);
+        }
+        assert_eq!(MOONLANDER.led_count(), 72);
+        assert!(!ERGODOX_EZ.has_rgb());
+    }
+
+    #[test]
+    fn find_models() {
+        assert_eq!(from_friendly_name("Voyager"), Some(&VOYAGER));
+        assert_eq!(from_friendly_name("Moonlander Mark I"), Some(&MOONLANDER));
+        assert_eq!(from_friendly_name("ErgoDox EZ Glow"), Some(&ERGODOX_EZ));
+        assert_eq!(from_friendly_name("Planck EZ"), None);
+        assert_eq!(from_name("Moonlander"), Some(&MOONLANDER));
+        assert_eq!(from_name("ergodox-ez"), Some(&ERGODOX_EZ));
+        assert_eq!(from_name("planck"), None);
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(VOYAGER.half(Half::Left), (0..26).collect::<Vec<_>>());
+        assert_eq!(VOYAGER.thumbs(None), vec![24, 25, 50, 51]);
+        assert_eq!(MOONLANDER.thumbs(Some(Half::Right)), vec![68, 69, 70, 71]);
+        assert_eq!(
+            VOYAGER.row(0),
+            vec![0, 1, 2, 3, 4, 5, 26, 27, 28, 29, 30, 31]
+        );
+        assert_eq!(MOONLANDER.col(0), vec![0, 1, 2, 3, 4]);
+        assert_eq!(VOYAGER.layout_leds()[..8], [0, 1, 2, 3, 4, 5, 26, 27]);
+        assert_eq!(VOYAGER.layout_leds()[48..], [24, 25, 50, 51]);
+
+        let led = VOYAGER.led(27).unwrap();
+        assert_eq!((led.row, led.col, led.half), (0, 7, Half::Right));
+        assert_eq!((led.x, led.y), (10.5, 0.75));
+    }
+
+    #[test]
+    fn parse_selectors() {
+        let parse = |s: &str| s.parse::<LedSelector>();
+        assert_eq!(parse("12"), Ok(LedSelector::Index(12)));
+        assert_eq!(parse("11-0"), Ok(LedSelector::Range(11, 0)));
+        assert_eq!(parse("R2C3"), Ok(LedSelector::Key { row: 2, col: 3 }));
+        assert_eq!(parse("row:1"), Ok(LedSelector::Row(1)));
+        assert_eq!(parse("col:0"), Ok(LedSelector::Col(0)));
+        assert_eq!(parse("left"), Ok(LedSelector::Half(Half::Left)));
+        assert_eq!(
+            parse("right-thumbs"),
+            Ok(LedSelector::Thumbs(Some(Half::Right)))
+        );
+        for invalid in ["", "r2", "rc3", "row:", "1-", "middle", "-1"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+        let selection: LedSelection = "left,r4c6,1-3".parse().unwrap();
+        assert_eq!(selection.to_string(), "left,r4c6,1-3");
+        assert!(selection.needs_model());
+        assert!(!"1,3-4".parse::<LedSelection>().unwrap().needs_model());
+    }
+
+    #[test]
+    fn resolve_selectors() {
+        let resolve = |s: &str, model| s.parse::<LedSelection>().unwrap().resolve(model);
+        assert_eq!(resolve("3-1,2,5", None), Ok(vec![3, 2, 1, 5]));
+        assert_eq!(resolve("left-thumbs", Some(&VOYAGER)), Ok(vec![24, 25]));
+        assert_eq!(
+            resolve("r2c3,thumbs", Some(&VOYAGER)),
+            Ok(vec![15, 24, 25, 50, 51])
+        );
+        assert_eq!(resolve("row:4", Some(&VOYAGER)), Ok(vec![24, 25, 50, 51]));
+        assert_eq!(resolve("all", Some(&MOONLANDER)).unwrap().len(), 72);
+        assert!(resolve("left", None).is_err());
+        assert!(resolve("r4c0", Some(&VOYAGER)).is_err());
+        assert!(resolve("left", Some(&ERGODOX_EZ)).is_err());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let resolve = |s: &str, model| s.parse::<LedSelection>().unwrap().resolve(model);
+        assert_eq!(resolve("255", None), Ok(vec![255]));
+        assert_eq!(resolve("250-255", None).unwrap().len(), 6);
+        assert!(resolve("256", None).is_err());
+        assert!(resolve("0-18446744073709551615", None).is_err());
+        assert!(resolve("200000-0", None).is_err());
+        assert_eq!(resolve("51", Some(&VOYAGER)), Ok(vec![51]));
+        assert!(resolve("52", Some(&VOYAGER)).is_err());
+        assert!(resolve("40-60", Some(&VOYAGER)).is_err());
+        assert!(resolve("0", Some(&ERGODOX_EZ)).is_err());
+    }
+}
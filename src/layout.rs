@@ -0,0 +1,489 @@
+//! Layouts exported from QMK or Oryx, to find the LEDs under the keys of each layer.
+//!
+//! Both QMK's `keymap.json`, as written by `qmk c2json` or the QMK configurator, and the JSON
+//! of an Oryx layout list the keycodes of each layer in the order of the keyboard's `LAYOUT`
+//! macro, which [`KeyboardModel::layout_leds`] maps to LEDs:
+//!
+//! ```rust
+//! use kontroll::keyboard::VOYAGER;
+//! use kontroll::layout::{KeyKind, Layout};
+//!
+//! let mut keys = vec!["KC_NO"; 52];
+//! keys[0] = "KC_ESC";
+//! keys[48] = "KC_LSFT";
+//! let json = format!(r#"{{"keyboard": "zsa/voyager", "layers": [{:?}]}}"#, keys);
+//! let layout: Layout = json.parse().unwrap();
+//!
+//! assert_eq!(layout.key_leds(&VOYAGER, 0, "KC_ESC").unwrap(), vec![0]);
+//! assert_eq!(layout.kind_leds(&VOYAGER, 0, Some(KeyKind::Modifier)).unwrap(), vec![24]);
+//! ```
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::keyboard::KeyboardModel;
+
+/// Errors loading a layout or finding its keys.
+#[derive(Debug)]
+pub enum LayoutError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The JSON is neither a QMK keymap nor an Oryx layout.
+    Format(String),
+    /// The layout doesn't have the layer.
+    NoLayer(usize),
+    /// The layout has a different number of keys than the keyboard.
+    Mismatch {
+        keyboard: &'static str,
+        keys: usize,
+        expected: usize,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::Io(e) => write!(f, "Failed to read the layout: {}", e),
+            LayoutError::Json(e) => write!(f, "Failed to parse the layout: {}", e),
+            LayoutError::Format(message) => write!(f, "Invalid layout: {}", message),
+            LayoutError::NoLayer(layer) => write!(f, "The layout has no layer {}", layer),
+            LayoutError::Mismatch {
+                keyboard,
+                keys,
+                expected,
+            } => write!(
+                f,
+                "The layout has {} keys, the {} has {} keys with an LED",
+                keys, keyboard, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+/// A kind of key, to select the keys of a layer by what they do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyKind {
+    /// Shift, Control, Alt and GUI, on their own, held on a mod-tap or one-shot.
+    Modifier,
+    /// Keys switching layers.
+    Layer,
+    /// A to Z.
+    Letter,
+    /// 0 to 9, on the number row or the keypad.
+    Number,
+    /// F1 to F24.
+    Function,
+    /// Keys falling through to the layer below.
+    Transparent,
+}
+
+impl KeyKind {
+    /// Returns whether a keycode is of this kind.
+    pub fn matches(&self, keycode: &str) -> bool {
+        let keycode = keycode.trim().to_uppercase();
+        let tap = basic_keycode(&keycode);
+        let key = tap.strip_prefix("KC_").unwrap_or(tap);
+        match self {
+            KeyKind::Modifier => {
+                is_modifier(key)
+                    || keycode.starts_with("MT(")
+                    || keycode.starts_with("OSM(")
+                    || keycode
+                        .split_once("_T(")
+                        .is_some_and(|(mods, _)| !mods.contains('('))
+                    || keycode
+                        .split_once('(')
+                        .is_some_and(|(mods, _)| is_modifier(mods))
+            }
+            KeyKind::Layer => ["MO(", "TG(", "TO(", "TT(", "OSL(", "DF(", "LT(", "LM("]
+                .iter()
+                .any(|prefix| keycode.starts_with(prefix)),
+            KeyKind::Letter => key.len() == 1 && key.as_bytes()[0].is_ascii_uppercase(),
+            KeyKind::Number => {
+                let key = key.strip_prefix("KP_").unwrap_or(key);
+                key.len() == 1 && key.as_bytes()[0].is_ascii_digit()
+            }
+            KeyKind::Function => key
+                .strip_prefix('F')
+                .and_then(|n| n.parse::<u8>().ok())
+                .is_some_and(|n| (1..=24).contains(&n)),
+            KeyKind::Transparent => is_transparent(&keycode),
+        }
+    }
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            KeyKind::Modifier => "modifiers",
+            KeyKind::Layer => "layers",
+            KeyKind::Letter => "letters",
+            KeyKind::Number => "numbers",
+            KeyKind::Function => "function",
+            KeyKind::Transparent => "transparent",
+        })
+    }
+}
+
+impl FromStr for KeyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().trim_end_matches('s') {
+            "modifier" | "mod" => Ok(KeyKind::Modifier),
+            "layer" => Ok(KeyKind::Layer),
+            "letter" => Ok(KeyKind::Letter),
+            "number" => Ok(KeyKind::Number),
+            "function" => Ok(KeyKind::Function),
+            "transparent" => Ok(KeyKind::Transparent),
+            _ => Err(format!(
+                "unknown kind of key {}, expected modifiers, layers, letters, numbers, function or transparent",
+                s
+            )),
+        }
+    }
+}
+
+// The modifiers, under their short and long QMK names.
+fn is_modifier(key: &str) -> bool {
+    let key = key.strip_prefix("KC_").unwrap_or(key);
+    let side = key
+        .strip_prefix("LEFT_")
+        .or_else(|| key.strip_prefix("RIGHT_"))
+        .or_else(|| key.strip_prefix('L'))
+        .or_else(|| key.strip_prefix('R'));
+    matches!(
+        side,
+        Some("CTL" | "CTRL" | "SFT" | "SHIFT" | "ALT" | "OPT" | "GUI" | "CMD" | "WIN" | "COMMAND")
+    ) || matches!(key, "HYPR" | "MEH")
+}
+
+fn is_transparent(keycode: &str) -> bool {
+    matches!(keycode, "KC_TRNS" | "KC_TRANSPARENT" | "_______")
+}
+
+fn is_unassigned(keycode: &str) -> bool {
+    let keycode = keycode.trim().to_uppercase();
+    keycode.is_empty()
+        || is_transparent(&keycode)
+        || matches!(keycode.as_str(), "KC_NO" | "XXXXXXX")
+}
+
+// The key tapped for keycodes wrapping another one, such as `LT(1, KC_SPC)` or `LSFT(KC_A)`.
+// Keycodes only taking a layer, such as `MO(1)`, are returned whole.
+fn basic_keycode(keycode: &str) -> &str {
+    let Some((_, args)) = keycode.split_once('(') else {
+        return keycode;
+    };
+    let args = args.strip_suffix(')').unwrap_or(args);
+    let last = args.rsplit(',').next().unwrap_or(args).trim();
+    if last.bytes().all(|b| b.is_ascii_digit()) {
+        keycode
+    } else {
+        basic_keycode(last)
+    }
+}
+
+/// Returns whether a keycode is the given key, or taps it, such as `LT(1, KC_ESC)` for
+/// `KC_ESC`. The `KC_` prefix is optional and case doesn't matter.
+pub fn is_key(keycode: &str, key: &str) -> bool {
+    let normalize = |keycode: &str| {
+        let keycode = keycode.trim().to_uppercase();
+        match keycode.strip_prefix("KC_") {
+            Some(key) => key.to_string(),
+            None => keycode,
+        }
+    };
+    let (keycode, key) = (normalize(keycode), normalize(key));
+    keycode == key || normalize(basic_keycode(&keycode)) == key
+}
+
+/// The keycodes of each layer of a keyboard, in the order of its `LAYOUT` macro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    layers: Vec<Vec<String>>,
+}
+
+impl Layout {
+    pub fn new(layers: Vec<Vec<String>>) -> Self {
+        Self { layers }
+    }
+
+    /// Reads a QMK `keymap.json` or an Oryx layout.
+    pub fn load(path: &Path) -> Result<Self, LayoutError> {
+        std::fs::read_to_string(path)
+            .map_err(LayoutError::Io)?
+            .parse()
+    }
+
+    /// Returns the keycodes of a layer.
+    pub fn layer(&self, layer: usize) -> Option<&[String]> {
+        self.layers.get(layer).map(Vec::as_slice)
+    }
+
+    /// Returns the number of layers.
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Returns the LEDs under the keys of a layer for which `f` returns true, by index.
+    pub fn leds(
+        &self,
+        model: &KeyboardModel,
+        layer: usize,
+        f: impl Fn(&str) -> bool,
+    ) -> Result<Vec<usize>, LayoutError> {
+        let keys = self.layer(layer).ok_or(LayoutError::NoLayer(layer))?;
+        let leds = model.layout_leds();
+        if keys.len() != leds.len() {
+            return Err(LayoutError::Mismatch {
+                keyboard: model.friendly_name(),
+                keys: keys.len(),
+                expected: leds.len(),
+            });
+        }
+        let mut leds: Vec<usize> = keys
+            .iter()
+            .zip(leds)
+            .filter(|(keycode, _)| f(keycode))
+            .map(|(_, led)| led)
+            .collect();
+        leds.sort();
+        Ok(leds)
+    }
+
+    /// Returns the LEDs under a key on a layer, see [`is_key`].
+    pub fn key_leds(
+        &self,
+        model: &KeyboardModel,
+        layer: usize,
+        key: &str,
+    ) -> Result<Vec<usize>, LayoutError> {
+        self.leds(model, layer, |keycode| is_key(keycode, key))
+    }
+
+    /// Returns the LEDs under the keys of a kind on a layer, or under every key assigned on the
+    /// layer, neither transparent nor `KC_NO`, without a kind.
+    pub fn kind_leds(
+        &self,
+        model: &KeyboardModel,
+        layer: usize,
+        kind: Option<KeyKind>,
+    ) -> Result<Vec<usize>, LayoutError> {
+        self.leds(model, layer, |keycode| match kind {
+            Some(kind) => kind.matches(keycode),
+            None => !is_unassigned(keycode),
+        })
+    }
+}
+
+// Oryx keys are objects, their keycode is the one sent on tap.
+fn keycode(key: &Value) -> Option<String> {
+    match key {
+        Value::String(keycode) => Some(keycode.clone()),
+        Value::Null => Some("KC_NO".to_string()),
+        Value::Object(key) => key
+            .get("code")
+            .or_else(|| key.get("tap").and_then(|tap| tap.get("code")))
+            .map_or(Some("KC_NO".to_string()), keycode),
+        _ => None,
+    }
+}
+
+impl FromStr for Layout {
+    type Err = LayoutError;
+
+    /// Parses a QMK `keymap.json`, with layers as lists of keycodes, or an Oryx layout, with
+    /// layers holding their `keys` as objects, possibly within `data.layout.revision`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json: Value = serde_json::from_str(s).map_err(LayoutError::Json)?;
+        let root = ["data", "layout", "revision"]
+            .iter()
+            .fold(&json, |value, key| {
+                value.get(key).filter(|v| v.is_object()).unwrap_or(value)
+            });
+        let layers = root
+            .get("layers")
+            .and_then(Value::as_array)
+            .ok_or_else(|| LayoutError::Format("no layers found".to_string()))?;
+        let layers = layers
+            .iter()
+            .enumerate()
+            .map(|(index, layer)| {
+                let keys = match layer {
+                    Value::Array(keys) => Some(keys),
+                    layer => layer.get("keys").and_then(Value::as_array),
+                };
+                keys.and_then(|keys| keys.iter().map(keycode).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| LayoutError::Format(format!("invalid keys on layer {}", index)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Layout::new(layers))
+    }
+}
+
+/// Selects the keys of a layer, of a kind or all those assigned: `2` or `2:modifiers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerKeys {
+    pub layer: usize,
+    pub kind: Option<KeyKind>,
+}
+
+impl LayerKeys {
+    /// Returns the LEDs under the selected keys.
+    pub fn leds(&self, layout: &Layout, model: &KeyboardModel) -> Result<Vec<usize>, LayoutError> {
+        layout.kind_leds(model, self.layer, self.kind)
+    }
+}
+
+impl fmt::Display for LayerKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Some(kind) => write!(f, "{}:{}", self.layer, kind),
+            None => write!(f, "{}", self.layer),
+        }
+    }
+}
+
+impl FromStr for LayerKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (layer, kind) = match s.split_once(':') {
+            Some((layer, kind)) => (layer, Some(kind.parse()?)),
+            None => (s, None),
+        };
+        let layer = layer
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} is not a valid layer", layer.trim()))?;
+        Ok(LayerKeys { layer, kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::VOYAGER;
+
+    #[test]
+    fn key_kinds() {
+        let kinds = |keycode: &str| {
+            [
+                KeyKind::Modifier,
+                KeyKind::Layer,
+                KeyKind::Letter,
+                KeyKind::Number,
+                KeyKind::Function,
+                KeyKind::Transparent,
+            ]
+            .into_iter()
+            .filter(|kind| kind.matches(keycode))
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds("KC_LSFT"), [KeyKind::Modifier]);
+        assert_eq!(kinds("KC_RIGHT_CTRL"), [KeyKind::Modifier]);
+        assert_eq!(kinds("KC_LCMD"), [KeyKind::Modifier]);
+        assert_eq!(kinds("LSFT_T(KC_A)"), [KeyKind::Modifier, KeyKind::Letter]);
+        assert_eq!(kinds("MT(MOD_LCTL, KC_ESC)"), [KeyKind::Modifier]);
+        assert_eq!(kinds("OSM(MOD_LSFT)"), [KeyKind::Modifier]);
+        assert_eq!(kinds("LGUI(KC_C)"), [KeyKind::Modifier, KeyKind::Letter]);
+        assert_eq!(kinds("LT(1,KC_SPC)"), [KeyKind::Layer]);
+        assert_eq!(kinds("MO(2)"), [KeyKind::Layer]);
+        assert_eq!(kinds("kc_q"), [KeyKind::Letter]);
+        assert_eq!(kinds("KC_KP_7"), [KeyKind::Number]);
+        assert_eq!(kinds("KC_F12"), [KeyKind::Function]);
+        assert_eq!(kinds("KC_F25"), []);
+        assert_eq!(kinds("_______"), [KeyKind::Transparent]);
+        assert_eq!(kinds("KC_SPC"), []);
+        assert_eq!("mods".parse(), Ok(KeyKind::Modifier));
+    }
+
+    #[test]
+    fn keys() {
+        assert!(is_key("KC_ESC", "kc_esc"));
+        assert!(is_key("KC_ESC", "ESC"));
+        assert!(is_key("LT(1, KC_ESC)", "KC_ESC"));
+        assert!(is_key("LCTL_T(KC_ESC)", "KC_ESC"));
+        assert!(!is_key("KC_ESC", "KC_A"));
+    }
+
+    fn voyager_keys(keys: &[(usize, &str)]) -> Vec<String> {
+        let mut layer = vec!["KC_TRNS".to_string(); 52];
+        for &(position, keycode) in keys {
+            layer[position] = keycode.to_string();
+        }
+        layer
+    }
+
+    #[test]
+    fn parse_qmk_keymap() {
+        let json = serde_json::json!({
+            "keyboard": "zsa/voyager",
+            "layout": "LAYOUT",
+            "layers": [voyager_keys(&[(0, "KC_ESC"), (6, "KC_ESC")]), voyager_keys(&[(48, "MO(1)")])],
+        });
+        let layout: Layout = json.to_string().parse().unwrap();
+        assert_eq!(layout.layer_count(), 2);
+        // The first key of the right half is LED 26, the first thumb key LED 24.
+        assert_eq!(layout.key_leds(&VOYAGER, 0, "KC_ESC").unwrap(), vec![0, 26]);
+        assert_eq!(layout.kind_leds(&VOYAGER, 1, None).unwrap(), vec![24]);
+        assert!(matches!(
+            layout.key_leds(&VOYAGER, 2, "KC_ESC"),
+            Err(LayoutError::NoLayer(2))
+        ));
+    }
+
+    #[test]
+    fn parse_oryx_layout() {
+        let keys: Vec<Value> = voyager_keys(&[(1, "KC_Q")])
+            .into_iter()
+            .map(|code| serde_json::json!({ "code": code, "layer": null }))
+            .collect();
+        let json = serde_json::json!({
+            "data": { "layout": { "revision": { "layers": [{ "title": "Base", "keys": keys }] } } }
+        });
+        let layout: Layout = json.to_string().parse().unwrap();
+        assert_eq!(
+            layout
+                .kind_leds(&VOYAGER, 0, Some(KeyKind::Letter))
+                .unwrap(),
+            vec![1]
+        );
+
+        let short: Layout = r#"{"layers": [["KC_A"]]}"#.parse().unwrap();
+        assert!(matches!(
+            short.kind_leds(&VOYAGER, 0, None),
+            Err(LayoutError::Mismatch { keys: 1, .. })
+        ));
+        assert!(matches!(
+            r#"{"keymap": "default"}"#.parse::<Layout>(),
+            Err(LayoutError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn parse_layer_keys() {
+        assert_eq!(
+            "2:modifiers".parse(),
+            Ok(LayerKeys {
+                layer: 2,
+                kind: Some(KeyKind::Modifier)
+            })
+        );
+        assert_eq!(
+            "1".parse(),
+            Ok(LayerKeys {
+                layer: 1,
+                kind: None
+            })
+        );
+        assert!("x:modifiers".parse::<LayerKeys>().is_err());
+        assert!("1:vowels".parse::<LayerKeys>().is_err());
+    }
+}
@@ -5,15 +5,46 @@
 //! ## Usage
 //! ```rust,no_run
 //! use kontroll::Kontroll;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
 //! // Note: The port is optional, if not provided, it will default to
 //! // "$CONFIG_DIR/.keymapp/keymapp.sock" on Unix and 50051 on Windows.
-//! let port = None
+//! let port = None;
 //! let api = Kontroll::new(port).await.unwrap();
 //! let keyboards = api.list_keyboards().await.unwrap();
+//! # }
 //! ```
 //! The above example will list all the keyboards connected to the system. You can
 //! check other available methods in the [`Kontroll`] struct.
+//!
+//! With the `blocking` feature enabled, `kontroll::blocking::Kontroll` offers the same methods without
+//! requiring an async runtime. The `sim` feature adds `kontroll::sim::Simulator`, a simulated
+//! Keymapp to test against, and the `daemon` feature adds `kontroll::daemon::Daemon`, sharing a
+//! single connection to Keymapp between clients.
 pub mod api;
+pub mod backend;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod color;
+pub mod compositor;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod daemon;
+pub mod keyboard;
+#[cfg(feature = "layout")]
+pub mod layout;
+pub mod retry;
+#[cfg(all(unix, feature = "server"))]
+pub mod server;
+#[cfg(feature = "session")]
+pub mod session;
+#[cfg(all(unix, feature = "sim"))]
+pub mod sim;
+pub mod transport;
 pub mod utils;
+pub mod watch;
 
-pub use api::Kontroll;
+pub use api::{ApiError, Kontroll, KontrollBuilder, Operation};
+pub use backend::KeyboardBackend;
+pub use retry::{ConnectionEvent, RetryPolicy};
+pub use transport::Transport;
+pub use watch::StatusEvent;
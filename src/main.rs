@@ -1,4 +1,8 @@
 mod cli;
+mod hooks;
+mod shell;
+#[cfg(unix)]
+mod tui;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
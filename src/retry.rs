@@ -0,0 +1,192 @@
+use std::error::Error;
+use std::future::Future;
+use std::time::Duration;
+
+use tonic::{Code, Status};
+
+/// Controls how calls failing because of the connection to Keymapp are retried.
+///
+/// Between attempts, Kontroll waits for an exponentially growing backoff and reconnects to
+/// Keymapp, so long-running programs survive Keymapp restarting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times a failed call is retried, 0 disables retries.
+    pub max_retries: u32,
+    /// The delay before the first retry.
+    pub initial_backoff: Duration,
+    /// The upper bound of the delay between two retries.
+    pub max_backoff: Duration,
+    /// The factor the delay is multiplied by after each retry.
+    pub multiplier: f64,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries, failed calls return their error immediately.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// A policy that retries up to `max_retries` times with the default backoff.
+    pub fn with_max_retries(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the delay to wait before the given retry, starting at 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(retry.min(i32::MAX as u32) as i32);
+        let backoff = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+    }
+
+    /// Performs a call until it succeeds or `should_retry` gives up on its error, waiting for
+    /// the backoff and reconnecting before each retry.
+    pub(crate) async fn run<T, E, F, Fut, R, RFut>(
+        &self,
+        mut call: F,
+        mut should_retry: impl FnMut(&E) -> bool,
+        mut reconnect: R,
+    ) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        R: FnMut() -> RFut,
+        RFut: Future<Output = ()>,
+    {
+        let mut retry = 0;
+        loop {
+            let err = match call().await {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+            if !should_retry(&err) || retry >= self.max_retries {
+                return Err(err);
+            }
+            tokio::time::sleep(self.backoff(retry)).await;
+            retry += 1;
+            reconnect().await;
+        }
+    }
+}
+
+/// Returns true for the gRPC codes of calls failing because Keymapp couldn't be reached.
+pub fn is_transport_code(code: Code) -> bool {
+    matches!(code, Code::Unavailable | Code::DeadlineExceeded)
+}
+
+/// Returns true for the statuses of calls failing because Keymapp couldn't be reached.
+///
+/// Tonic reports a channel that can't be driven anymore as `Unknown`, which is also the code of
+/// any error returned by the server: those only count when caused by a connection error.
+pub fn is_transport_status(status: &Status) -> bool {
+    if is_transport_code(status.code()) {
+        return true;
+    }
+    if status.code() != Code::Unknown {
+        return false;
+    }
+    let mut source = status.source();
+    while let Some(err) = source {
+        if err.is::<hyper::Error>() || err.is::<tonic::transport::Error>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Returns true for the gRPC codes of calls that never reached Keymapp, tonic failing to
+/// connect reports `Unavailable`. Only those can be retried for calls that mustn't be applied
+/// twice, with the other transport codes Keymapp may have received the call.
+pub(crate) fn is_unsent_code(code: Code) -> bool {
+    code == Code::Unavailable
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+        }
+    }
+}
+
+/// Changes of the connection to Keymapp, reported to the listener set with
+/// [`KontrollBuilder::on_connection_change`](crate::KontrollBuilder::on_connection_change).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// A call failed because Keymapp could not be reached.
+    Lost,
+    /// Kontroll reconnected to Keymapp after the connection was lost.
+    Restored,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_until_max() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            multiplier: 2.0,
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn run_retries_until_success_or_max() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let mut attempts = 0;
+        let mut reconnects = 0;
+        let result: Result<(), Code> = policy
+            .run(
+                || {
+                    attempts += 1;
+                    async { Err(Code::Unavailable) }
+                },
+                |&code| is_transport_code(code),
+                || {
+                    reconnects += 1;
+                    async {}
+                },
+            )
+            .await;
+        assert_eq!(result, Err(Code::Unavailable));
+        assert_eq!((attempts, reconnects), (3, 2));
+
+        let mut attempts = 0;
+        let result: Result<(), Code> = policy
+            .run(
+                || {
+                    attempts += 1;
+                    async { Err(Code::InvalidArgument) }
+                },
+                |&code| is_transport_code(code),
+                || async {},
+            )
+            .await;
+        assert_eq!(result, Err(Code::InvalidArgument));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,41 @@
+//! Serving Keymapp's API from a [`KeyboardBackend`], so Kontroll and other Keymapp clients can
+//! connect to it. Used by the simulator and the recording proxy.
+use std::future::Future;
+use std::io;
+
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::Server;
+
+use crate::api::keymapp::keyboard_service_server::{KeyboardService, KeyboardServiceServer};
+use crate::backend::{BackendService, KeyboardBackend};
+
+/// Serves a backend on a bound Unix socket, until the future is dropped.
+pub async fn serve<B: KeyboardBackend + 'static>(
+    backend: B,
+    listener: UnixListener,
+) -> io::Result<()> {
+    serve_with_shutdown(backend, listener, std::future::pending()).await
+}
+
+/// Serves a backend on a bound Unix socket, until `signal` completes.
+pub async fn serve_with_shutdown<B: KeyboardBackend + 'static>(
+    backend: B,
+    listener: UnixListener,
+    signal: impl Future<Output = ()>,
+) -> io::Result<()> {
+    serve_service(BackendService::new(backend).into_server(), listener, signal).await
+}
+
+// Lets the daemon serve its own service, which needs the requests' metadata.
+pub(crate) async fn serve_service<S: KeyboardService>(
+    service: KeyboardServiceServer<S>,
+    listener: UnixListener,
+    signal: impl Future<Output = ()>,
+) -> io::Result<()> {
+    Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(UnixListenerStream::new(listener), signal)
+        .await
+        .map_err(io::Error::other)
+}
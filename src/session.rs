@@ -0,0 +1,491 @@
+//! Sessions of calls made to Keymapp, stored as JSON lines.
+//!
+//! Each line is an [`Entry`] holding when the call was made, the request and Keymapp's reply:
+//!
+//! ```json
+//! {"timestamp_ms":1718000000000,"request":{"method":"SetLayer","layer":2},"response":{"success":true}}
+//! ```
+//!
+//! Sessions are written by [`Recorder`], which the `kontroll proxy` command puts between a
+//! client and Keymapp, and can be played back on any keyboard with [`replay`].
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tonic::Status;
+
+use crate::api::keymapp::{
+    BrightnessUpdateReply, ConnectKeyboardReply, ConnectKeyboardRequest, DisconnectKeyboardReply,
+    GetKeyboardsReply, GetStatusReply, SetLayerReply, SetLayerRequest, SetRgbAllReply,
+    SetRgbAllRequest, SetRgbLedReply, SetRgbLedRequest, SetStatusLedReply, SetStatusLedRequest,
+};
+use crate::api::{ApiError, Kontroll};
+use crate::backend::{Call, KeyboardBackend, RESTORE_RGB_LEDS};
+
+/// A call recorded in a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// When the call was made, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub request: Call,
+    pub response: Reply,
+}
+
+/// Keymapp's reply to a recorded call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Status(GetStatusReply),
+    Keyboards(GetKeyboardsReply),
+    Success {
+        success: bool,
+    },
+    /// The call failed, with the gRPC status code and message.
+    Error {
+        code: i32,
+        message: String,
+    },
+}
+
+impl<T> From<&Result<T, Status>> for Reply
+where
+    T: Clone + Into<Reply>,
+{
+    fn from(result: &Result<T, Status>) -> Self {
+        match result {
+            Ok(reply) => reply.clone().into(),
+            Err(status) => Reply::Error {
+                code: status.code() as i32,
+                message: status.message().to_string(),
+            },
+        }
+    }
+}
+
+impl From<GetStatusReply> for Reply {
+    fn from(reply: GetStatusReply) -> Self {
+        Reply::Status(reply)
+    }
+}
+
+impl From<GetKeyboardsReply> for Reply {
+    fn from(reply: GetKeyboardsReply) -> Self {
+        Reply::Keyboards(reply)
+    }
+}
+
+macro_rules! success_reply {
+    ($($reply:ty),*) => {
+        $(impl From<$reply> for Reply {
+            fn from(reply: $reply) -> Self {
+                Reply::Success {
+                    success: reply.success,
+                }
+            }
+        })*
+    };
+}
+
+success_reply!(
+    ConnectKeyboardReply,
+    DisconnectKeyboardReply,
+    SetLayerReply,
+    SetRgbLedReply,
+    SetRgbAllReply,
+    SetStatusLedReply,
+    BrightnessUpdateReply
+);
+
+/// Reads a session, skipping blank lines.
+pub fn read(reader: impl BufRead) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |e: &dyn std::fmt::Display| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, e),
+            )
+        };
+        let entry: Entry = serde_json::from_str(&line).map_err(|e| invalid(&e))?;
+        if let Some((red, green, blue)) = color(&entry.request) {
+            rgb(red, green, blue).map_err(|e| invalid(&e))?;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The slowest replay speed, a hundred times slower than recorded.
+pub const MIN_REPLAY_SPEED: f64 = 0.01;
+
+/// Re-issues the calls changing the keyboard's lighting and layers through `api`, waiting
+/// between calls as long as they were apart when recorded, divided by `speed`, which must be
+/// at least [`MIN_REPLAY_SPEED`].
+///
+/// Only `SetRgbLed`, `SetRgbAll`, `SetLayer`, `UnsetLayer`, `SetStatusLed` and brightness calls
+/// are replayed, other calls are skipped but still count for timing. Returns the number of calls
+/// replayed.
+pub async fn replay(api: &Kontroll, entries: &[Entry], speed: f64) -> Result<usize, ApiError> {
+    if !speed.is_finite() || speed < MIN_REPLAY_SPEED {
+        return Err(ApiError::InvalidArgument(format!(
+            "replay speed must be at least {}, got {}",
+            MIN_REPLAY_SPEED, speed
+        )));
+    }
+    let Some(first) = entries.first() else {
+        return Ok(0);
+    };
+    let start = tokio::time::Instant::now();
+    let mut replayed = 0;
+    for entry in entries {
+        if !is_replayable(&entry.request) {
+            continue;
+        }
+        // Scheduling from the start of the session rather than the previous call keeps the
+        // time spent on calls from adding up.
+        let offset = entry.timestamp_ms.saturating_sub(first.timestamp_ms);
+        let deadline = Duration::try_from_secs_f64(offset as f64 / 1000.0 / speed)
+            .ok()
+            .and_then(|delay| start.checked_add(delay))
+            .ok_or_else(|| {
+                ApiError::InvalidArgument(format!(
+                    "a call {}ms into the session is too late to replay at speed {}",
+                    offset, speed
+                ))
+            })?;
+        tokio::time::sleep_until(deadline).await;
+        replay_call(api, &entry.request).await?;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+fn is_replayable(call: &Call) -> bool {
+    matches!(
+        call,
+        Call::SetRgbLed(_)
+            | Call::SetRgbAll(_)
+            | Call::SetLayer(_)
+            | Call::UnsetLayer(_)
+            | Call::SetStatusLed(_)
+            | Call::IncreaseBrightness
+            | Call::DecreaseBrightness
+    )
+}
+
+fn index(value: i32, what: &str) -> Result<usize, ApiError> {
+    usize::try_from(value)
+        .map_err(|_| ApiError::InvalidArgument(format!("invalid {} {} in session", what, value)))
+}
+
+fn channel(value: i32, what: &str) -> Result<u8, ApiError> {
+    u8::try_from(value).map_err(|_| {
+        ApiError::InvalidArgument(format!("invalid {} channel {} in session", what, value))
+    })
+}
+
+fn rgb(red: i32, green: i32, blue: i32) -> Result<(u8, u8, u8), ApiError> {
+    Ok((
+        channel(red, "red")?,
+        channel(green, "green")?,
+        channel(blue, "blue")?,
+    ))
+}
+
+// The color channels of the calls setting RGB LEDs.
+fn color(call: &Call) -> Option<(i32, i32, i32)> {
+    match call {
+        Call::SetRgbLed(r) => Some((r.red, r.green, r.blue)),
+        Call::SetRgbAll(r) => Some((r.red, r.green, r.blue)),
+        _ => None,
+    }
+}
+
+async fn replay_call(api: &Kontroll, call: &Call) -> Result<bool, ApiError> {
+    match call {
+        Call::SetRgbLed(r) => {
+            let led = index(r.led, "LED")?;
+            let (red, green, blue) = rgb(r.red, r.green, r.blue)?;
+            api.set_rgb_led(led, red, green, blue, r.sustain).await
+        }
+        Call::SetRgbAll(r) => {
+            let (red, green, blue) = rgb(r.red, r.green, r.blue)?;
+            api.set_rgb_all(red, green, blue, r.sustain).await
+        }
+        Call::SetLayer(r) => api.set_layer(index(r.layer, "layer")?).await,
+        Call::UnsetLayer(r) => api.unset_layer(index(r.layer, "layer")?).await,
+        Call::SetStatusLed(r) => {
+            let led = index(r.led, "status LED")?;
+            api.set_status_led(led, r.on, r.sustain).await
+        }
+        Call::IncreaseBrightness => api.update_brightness(true, 1).await,
+        Call::DecreaseBrightness => api.update_brightness(false, 1).await,
+        _ => Ok(false),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A backend forwarding every call to another backend and writing it to a session.
+///
+/// Recording is best effort, failing to write an entry doesn't fail the forwarded call. Each
+/// entry is flushed as soon as it is written, so the session survives the recorder being killed.
+pub struct Recorder<B, W> {
+    backend: B,
+    out: Mutex<W>,
+}
+
+impl<B: KeyboardBackend, W: Write + Send> Recorder<B, W> {
+    pub fn new(backend: B, out: W) -> Self {
+        Self {
+            backend,
+            out: Mutex::new(out),
+        }
+    }
+
+    /// Returns the writer the session is written to.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+
+    fn record<T>(&self, timestamp_ms: u64, request: Call, result: &Result<T, Status>)
+    where
+        T: Clone + Into<Reply>,
+    {
+        let entry = Entry {
+            timestamp_ms,
+            request,
+            response: result.into(),
+        };
+        let mut out = self.out.lock().unwrap();
+        if let Ok(line) = serde_json::to_string(&entry) {
+            let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<B: KeyboardBackend, W: Write + Send> KeyboardBackend for Recorder<B, W> {
+    async fn get_status(&self) -> Result<GetStatusReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.get_status().await;
+        self.record(timestamp_ms, Call::GetStatus, &result);
+        result
+    }
+
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.get_keyboards().await;
+        self.record(timestamp_ms, Call::GetKeyboards, &result);
+        result
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.connect_keyboard(request.clone()).await;
+        self.record(timestamp_ms, Call::ConnectKeyboard(request), &result);
+        result
+    }
+
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.connect_any_keyboard().await;
+        self.record(timestamp_ms, Call::ConnectAnyKeyboard, &result);
+        result
+    }
+
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.disconnect_keyboard().await;
+        self.record(timestamp_ms, Call::DisconnectKeyboard, &result);
+        result
+    }
+
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.set_layer(request.clone()).await;
+        self.record(timestamp_ms, Call::SetLayer(request), &result);
+        result
+    }
+
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.unset_layer(request.clone()).await;
+        self.record(timestamp_ms, Call::UnsetLayer(request), &result);
+        result
+    }
+
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.set_rgb_led(request.clone()).await;
+        self.record(timestamp_ms, Call::SetRgbLed(request), &result);
+        result
+    }
+
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.set_rgb_all(request.clone()).await;
+        self.record(timestamp_ms, Call::SetRgbAll(request), &result);
+        result
+    }
+
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.set_status_led(request.clone()).await;
+        self.record(timestamp_ms, Call::SetStatusLed(request), &result);
+        result
+    }
+
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.increase_brightness().await;
+        self.record(timestamp_ms, Call::IncreaseBrightness, &result);
+        result
+    }
+
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.decrease_brightness().await;
+        self.record(timestamp_ms, Call::DecreaseBrightness, &result);
+        result
+    }
+
+    // Recorded as the call Keymapp receives.
+    async fn restore_rgb_leds(&self) -> Result<SetRgbAllReply, Status> {
+        let timestamp_ms = now_ms();
+        let result = self.backend.restore_rgb_leds().await;
+        self.record(timestamp_ms, Call::SetRgbAll(RESTORE_RGB_LEDS), &result);
+        result
+    }
+
+    async fn reconnect(&self) -> Result<(), ApiError> {
+        self.backend.reconnect().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::backend::RecordingBackend;
+    use crate::Kontroll;
+
+    #[tokio::test]
+    async fn sessions_round_trip() {
+        let backend = RecordingBackend::new();
+        backend.fail_next(Status::unavailable("keymapp restarting"));
+        let recorder = Arc::new(Recorder::new(backend, Vec::new()));
+        let api = Kontroll::with_backend(recorder.clone());
+
+        assert!(api.set_layer(2).await.is_err());
+        api.set_rgb_led(12, 255, 0, 0, 500).await.unwrap();
+        api.get_status().await.unwrap();
+        drop(api);
+
+        let recorder = Arc::into_inner(recorder).unwrap();
+        let session = recorder.into_inner();
+        let entries = read(session.as_slice()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].request,
+            Call::SetLayer(SetLayerRequest { layer: 2 })
+        );
+        assert_eq!(
+            entries[0].response,
+            Reply::Error {
+                code: tonic::Code::Unavailable as i32,
+                message: "keymapp restarting".to_string()
+            }
+        );
+        assert_eq!(entries[1].response, Reply::Success { success: true });
+        assert!(matches!(entries[2].response, Reply::Status(_)));
+    }
+
+    fn entry(timestamp_ms: u64, request: Call) -> Entry {
+        Entry {
+            timestamp_ms,
+            request,
+            response: Reply::Success { success: true },
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_timing() {
+        let backend = RecordingBackend::new();
+        let api = Kontroll::with_backend(backend.clone());
+        let led = SetRgbLedRequest {
+            led: 3,
+            red: 255,
+            green: 0,
+            blue: 0,
+            sustain: 0,
+        };
+        let entries = vec![
+            entry(1_000, Call::SetLayer(SetLayerRequest { layer: 1 })),
+            entry(1_050, Call::GetStatus),
+            entry(1_100, Call::SetRgbLed(led.clone())),
+        ];
+
+        let started = std::time::Instant::now();
+        assert_eq!(replay(&api, &entries, 2.0).await.unwrap(), 2);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            backend.calls(),
+            vec![
+                Call::SetLayer(SetLayerRequest { layer: 1 }),
+                Call::SetRgbLed(led)
+            ]
+        );
+        assert!(replay(&api, &entries, 0.0).await.is_err());
+        assert!(replay(&api, &entries, 1e-300).await.is_err());
+    }
+
+    #[test]
+    fn read_reports_the_invalid_line() {
+        let session = "\n{\"timestamp_ms\":0,\"request\":{\"method\":\"GetStatus\"},\"response\":{\"success\":true}}\nnot json\n";
+        let err = read(session.as_bytes()).unwrap_err();
+        assert!(err.to_string().starts_with("line 3:"));
+    }
+
+    #[tokio::test]
+    async fn rejects_colors_out_of_range() {
+        let session = "{\"timestamp_ms\":0,\"request\":{\"method\":\"SetRgbAll\",\"red\":256,\"green\":0,\"blue\":0,\"sustain\":0},\"response\":{\"success\":true}}\n";
+        let err = read(session.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: invalid red channel 256 in session"
+        );
+
+        let backend = RecordingBackend::new();
+        let api = Kontroll::with_backend(backend.clone());
+        let entries = vec![entry(
+            0,
+            Call::SetRgbLed(SetRgbLedRequest {
+                led: 0,
+                red: 0,
+                green: -1,
+                blue: 0,
+                sustain: 0,
+            }),
+        )];
+        assert!(replay(&api, &entries, 1.0).await.is_err());
+        assert!(backend.calls().is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! An interactive shell running commands over a single connection, with history and tab
+//! completion.
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use clap::CommandFactory;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use kontroll::Kontroll;
+
+use crate::cli::{self, CliError, Line};
+
+const PROMPT: &str = "kontroll> ";
+
+/// Short forms of the most used commands, with the flags their arguments are passed to.
+const SHORTHANDS: &[(&str, &str, &[&str])] = &[
+    ("rgb", "set-rgb", &["--led", "--color", "--sustain"]),
+    ("all", "set-rgb-all", &["--color", "--sustain"]),
+    ("restore", "restore-rgb-leds", &[]),
+    ("led", "set-status-led", &["--led"]),
+    ("layer", "set-layer", &["--index"]),
+    ("unset", "unset-layer", &["--index"]),
+    ("push", "push-layer", &["--index"]),
+    ("pop", "pop-layer", &[]),
+    ("connect", "connect", &["--index"]),
+    ("brighter", "increase-brightness", &["--steps"]),
+    ("dimmer", "decrease-brightness", &["--steps"]),
+];
+
+const SHORTHAND_HELP: &str = "Shorthands:
+  rgb <led> <color> [sustain]  set-rgb
+  all <color> [sustain]        set-rgb-all
+  restore                      restore-rgb-leds
+  led <led> [on|off]           set-status-led
+  layer <index>                set-layer
+  unset <index>                unset-layer
+  push <index>                 push-layer
+  pop                          pop-layer
+  connect <index>              connect
+  brighter [steps]             increase-brightness
+  dimmer [steps]               decrease-brightness
+  exit, quit                   leave the shell";
+
+// Commands that can't run within the shell, left out of the completions.
+const EXCLUDED: &[&str] = &[
+    "batch", "shell", "watch", "on-layer", "sim", "proxy", "daemon",
+];
+
+/// Rewrites the words of a shorthand into the full command, other lines are returned as they
+/// are.
+fn expand(words: Vec<String>) -> Vec<String> {
+    let shorthand = words
+        .first()
+        .and_then(|first| SHORTHANDS.iter().find(|(short, ..)| short == first));
+    let Some((_, command, flags)) = shorthand else {
+        return words;
+    };
+
+    let mut expanded = vec![command.to_string()];
+    let mut words = words.into_iter().skip(1).peekable();
+    // Arguments are given in order, until one is passed with its flag.
+    for flag in flags.iter() {
+        match words.next_if(|word| !word.starts_with('-')) {
+            Some(word) => expanded.extend([flag.to_string(), word]),
+            None => break,
+        }
+    }
+    for word in words {
+        match (*command, word.as_str()) {
+            ("set-status-led", "on") => {}
+            ("set-status-led", "off") => expanded.push("--off".to_string()),
+            _ => expanded.push(word),
+        }
+    }
+    expanded
+}
+
+/// Completes command names, shorthands included, and the long flags of the command being typed.
+struct ShellHelper {
+    command: clap::Command,
+    names: Vec<String>,
+}
+
+impl ShellHelper {
+    fn new() -> Self {
+        let command = Line::command();
+        let mut names: Vec<String> = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .filter(|name| !EXCLUDED.contains(&name.as_str()))
+            .chain(SHORTHANDS.iter().map(|(short, ..)| short.to_string()))
+            .chain(["help", "exit", "quit"].map(String::from))
+            .collect();
+        names.sort();
+        names.dedup();
+        Self { command, names }
+    }
+
+    fn candidates(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let candidates = if line[..start].trim().is_empty() {
+            self.names
+                .iter()
+                .filter(|name| name.starts_with(word))
+                .cloned()
+                .collect()
+        } else if word.starts_with('-') {
+            let first = line.split_whitespace().next().unwrap_or_default();
+            let name = SHORTHANDS
+                .iter()
+                .find(|(short, ..)| *short == first)
+                .map_or(first, |(_, command, _)| command);
+            self.command
+                .find_subcommand(name)
+                .map(|subcommand| {
+                    subcommand
+                        .get_arguments()
+                        .filter_map(|arg| arg.get_long())
+                        .map(|long| format!("--{}", long))
+                        .filter(|flag| flag.starts_with(word))
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        (start, candidates)
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    #[cfg(not(target_os = "windows"))]
+    return directories::BaseDirs::new().map(|dirs| dirs.home_dir().join(".kontroll_history"));
+    #[cfg(target_os = "windows")]
+    None
+}
+
+/// Reports a lost connection and tries to establish it again, returns false if it's still down.
+async fn reconnect(api: &Kontroll) -> bool {
+    match api.reconnect().await {
+        Ok(()) => {
+            eprintln!("Reconnected to Keymapp");
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to reconnect to Keymapp: {}", e);
+            false
+        }
+    }
+}
+
+/// Reads commands until `exit` or the end of the input, printing each result. Failed commands
+/// don't end the shell, a lost connection is established again before the next command.
+pub async fn run(api: &Kontroll) -> i32 {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    editor.set_helper(Some(ShellHelper::new()));
+    // Commands piped to the shell aren't worth remembering.
+    let history = history_path().filter(|_| std::io::stdin().is_terminal());
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut connected = true;
+    let code = loop {
+        let line = match tokio::task::block_in_place(|| editor.readline(PROMPT)) {
+            Ok(line) => line,
+            // Ctrl+C drops the line being typed, like in other shells.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                break 1;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        match line {
+            "exit" | "quit" => break 0,
+            "help" => {
+                println!("{}\n\n{}", Line::command().render_help(), SHORTHAND_HELP);
+                continue;
+            }
+            _ => {}
+        }
+
+        if !connected {
+            connected = reconnect(api).await;
+            if !connected {
+                continue;
+            }
+        }
+        let result = match cli::split_line(line).and_then(|words| cli::parse_words(expand(words))) {
+            Ok(Some(command)) => cli::execute(api, command).await,
+            Ok(None) => continue,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                eprintln!("{}", e);
+                if matches!(&e, CliError::Api(e) if e.is_transport()) {
+                    eprintln!("Lost the connection to Keymapp, reconnecting");
+                    connected = reconnect(api).await;
+                }
+            }
+        }
+    };
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_shorthands() {
+        let expand = |line: &str| expand(cli::split_line(line).unwrap()).join(" ");
+        assert_eq!(expand("rgb 12 #ff0000"), "set-rgb --led 12 --color #ff0000");
+        assert_eq!(
+            expand("rgb 12 --color red --sustain 500"),
+            "set-rgb --led 12 --color red --sustain 500"
+        );
+        assert_eq!(expand("layer 2"), "set-layer --index 2");
+        assert_eq!(expand("led 1 off"), "set-status-led --led 1 --off");
+        assert_eq!(expand("led 1 on"), "set-status-led --led 1");
+        assert_eq!(expand("pop"), "pop-layer");
+        assert_eq!(expand("status --json"), "status --json");
+        assert_eq!(
+            super::expand(cli::split_line("all 'rgb(10, 20, 30)' 500").unwrap()),
+            [
+                "set-rgb-all",
+                "--color",
+                "rgb(10, 20, 30)",
+                "--sustain",
+                "500"
+            ]
+        );
+    }
+
+    #[test]
+    fn complete_commands_and_flags() {
+        let helper = ShellHelper::new();
+        let (start, candidates) = helper.candidates("set-rgb");
+        assert_eq!(start, 0);
+        assert_eq!(candidates, ["set-rgb", "set-rgb-all"]);
+        assert!(!helper.candidates("s").1.contains(&"sim".to_string()));
+
+        let (start, candidates) = helper.candidates("rgb 12 --s");
+        assert_eq!(start, 7);
+        assert_eq!(candidates, ["--sustain"]);
+    }
+}
@@ -0,0 +1,438 @@
+//! A simulated Keymapp, serving the same API over a Unix socket while keeping the state of a
+//! virtual keyboard in memory.
+//!
+//! The simulator lets Kontroll and the programs built on it be tested without Keymapp or a
+//! keyboard:
+//!
+//! ```rust
+//! use kontroll::sim::Simulator;
+//!
+//! let sim = Simulator::new();
+//! let server = sim.start().unwrap();
+//! // Point Kontroll at `server.socket_path()`, for example with `kontroll --port`.
+//! # drop(server);
+//! ```
+//!
+//! It can also be used in-process as a [`KeyboardBackend`] with
+//! [`Kontroll::with_backend`](crate::Kontroll::with_backend).
+use std::io;
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use tempfile::TempDir;
+use tokio::net::UnixListener;
+use tokio::sync::{oneshot, watch};
+use tonic::Status;
+
+use crate::api::keymapp::{
+    BrightnessUpdateReply, ConnectKeyboardReply, ConnectKeyboardRequest, ConnectedKeyboard,
+    DisconnectKeyboardReply, GetKeyboardsReply, GetStatusReply, Keyboard, SetLayerReply,
+    SetLayerRequest, SetRgbAllReply, SetRgbAllRequest, SetRgbLedReply, SetRgbLedRequest,
+    SetStatusLedReply, SetStatusLedRequest,
+};
+use crate::backend::KeyboardBackend;
+use crate::keyboard::{self, KeyboardModel};
+use crate::server;
+use crate::transport::Transport;
+
+/// The brightness the simulated keyboard starts with.
+pub const DEFAULT_BRIGHTNESS: u8 = 128;
+// QMK's default RGB_MATRIX_VAL_STEP.
+const BRIGHTNESS_STEP: u8 = 16;
+
+struct State {
+    model: &'static KeyboardModel,
+    keyboards: Vec<Keyboard>,
+    connected: Option<usize>,
+    // Layers set and not unset yet, the last one is the current layer.
+    layers: Vec<i32>,
+    rgb_leds: Vec<(u8, u8, u8)>,
+    status_leds: Vec<bool>,
+    brightness: u8,
+    fail_next_request: bool,
+}
+
+impl State {
+    // Returns the same error type as the backend calls it is used by.
+    #[allow(clippy::result_large_err)]
+    fn begin(&mut self) -> Result<(), Status> {
+        if std::mem::take(&mut self.fail_next_request) {
+            return Err(Status::unavailable("simulated failure"));
+        }
+        Ok(())
+    }
+
+    fn connect(&mut self, index: usize) -> bool {
+        if index >= self.keyboards.len() {
+            return false;
+        }
+        self.disconnect();
+        self.keyboards[index].is_connected = true;
+        self.connected = Some(index);
+        true
+    }
+
+    fn disconnect(&mut self) -> bool {
+        match self.connected.take() {
+            Some(index) => {
+                self.keyboards[index].is_connected = false;
+                self.layers.clear();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A simulated Keymapp with a single keyboard available.
+///
+/// Clones share the same state, so a clone can be served while the original is used to
+/// inspect the keyboard or inject faults. LED sustain is accepted but not simulated, colors
+/// stay until they are changed.
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<State>>,
+    changes: Arc<watch::Sender<()>>,
+}
+
+/// The state of the simulated keyboard at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub model: &'static KeyboardModel,
+    pub connected: bool,
+    /// The current layer, 0 when no layer is set.
+    pub layer: i32,
+    pub rgb_leds: Vec<(u8, u8, u8)>,
+    pub status_leds: Vec<bool>,
+    pub brightness: u8,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    /// Creates a simulator with a disconnected Voyager, all LEDs off.
+    pub fn new() -> Self {
+        Self::with_model(&keyboard::VOYAGER)
+    }
+
+    /// Creates a simulator with a disconnected keyboard of the given model, all LEDs off.
+    pub fn with_model(model: &'static KeyboardModel) -> Self {
+        let keyboards = vec![Keyboard {
+            id: 0,
+            friendly_name: model.friendly_name().to_string(),
+            is_connected: false,
+        }];
+        Self {
+            state: Arc::new(Mutex::new(State {
+                model,
+                keyboards,
+                connected: None,
+                layers: Vec::new(),
+                rgb_leds: vec![(0, 0, 0); model.led_count()],
+                status_leds: vec![false; model.status_led_count()],
+                brightness: DEFAULT_BRIGHTNESS,
+                fail_next_request: false,
+            })),
+            changes: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    /// Returns the model of the simulated keyboard.
+    pub fn model(&self) -> &'static KeyboardModel {
+        self.state.lock().unwrap().model
+    }
+
+    /// Makes the next request fail as if Keymapp could not be reached.
+    pub fn fail_next_request(&self, fail: bool) {
+        self.state.lock().unwrap().fail_next_request = fail;
+    }
+
+    /// Connects the keyboard at the given index, as if it was connected from Keymapp.
+    pub fn set_connected_index(&self, index: usize) {
+        self.state.lock().unwrap().connect(index);
+        self.changes.send_replace(());
+    }
+
+    /// Returns a receiver notified every time the keyboard's state may have changed.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    /// Returns the current state of the keyboard.
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state.lock().unwrap();
+        Snapshot {
+            model: state.model,
+            connected: state.connected.is_some(),
+            layer: state.layers.last().copied().unwrap_or(0),
+            rgb_leds: state.rgb_leds.clone(),
+            status_leds: state.status_leds.clone(),
+            brightness: state.brightness,
+        }
+    }
+
+    /// Returns the connected keyboard.
+    pub fn connected_keyboard(&self) -> Option<Keyboard> {
+        let state = self.state.lock().unwrap();
+        state.connected.map(|index| state.keyboards[index].clone())
+    }
+
+    /// Returns the current layer, 0 when no layer is set.
+    pub fn current_layer(&self) -> i32 {
+        self.state
+            .lock()
+            .unwrap()
+            .layers
+            .last()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the layers set and not unset yet, the current layer last.
+    pub fn layers(&self) -> Vec<i32> {
+        self.state.lock().unwrap().layers.clone()
+    }
+
+    /// Returns the color of an RGB LED.
+    pub fn rgb_led(&self, led: usize) -> Option<(u8, u8, u8)> {
+        self.state.lock().unwrap().rgb_leds.get(led).copied()
+    }
+
+    /// Returns whether a status LED is on.
+    pub fn status_led(&self, led: usize) -> Option<bool> {
+        self.state.lock().unwrap().status_leds.get(led).copied()
+    }
+
+    /// Returns the brightness of the keyboard's LEDs.
+    pub fn brightness(&self) -> u8 {
+        self.state.lock().unwrap().brightness
+    }
+
+    /// Serves the simulator on a bound Unix socket, until the future is dropped.
+    pub async fn serve(self, listener: UnixListener) -> io::Result<()> {
+        server::serve(self, listener).await
+    }
+
+    /// Serves the simulator on a temporary socket from a background thread, until the returned
+    /// server is dropped.
+    pub fn start(&self) -> io::Result<SimServer> {
+        let dir = tempfile::tempdir()?;
+        let socket_path = dir.path().join("keymapp.sock");
+        let listener = StdUnixListener::bind(&socket_path)?;
+        listener.set_nonblocking(true)?;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (shutdown, stop) = oneshot::channel::<()>();
+        let sim = self.clone();
+        let thread = std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let listener = UnixListener::from_std(listener)?;
+                server::serve_with_shutdown(sim, listener, async {
+                    let _ = stop.await;
+                })
+                .await
+            })
+        });
+
+        Ok(SimServer {
+            _dir: dir,
+            socket_path,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    // Applies a request changing the keyboard's state and notifies the subscribers.
+    #[allow(clippy::result_large_err)]
+    fn update<T>(&self, f: impl FnOnce(&mut State) -> T) -> Result<T, Status> {
+        let result = {
+            let mut state = self.state.lock().unwrap();
+            state.begin()?;
+            f(&mut state)
+        };
+        self.changes.send_replace(());
+        Ok(result)
+    }
+}
+
+/// A simulator served from a background thread, stopped when dropped.
+pub struct SimServer {
+    // Removes the socket once the server is dropped.
+    _dir: TempDir,
+    socket_path: PathBuf,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl SimServer {
+    /// Returns the path of the socket the simulator listens on.
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Returns the transport connecting to the simulator.
+    pub fn transport(&self) -> Transport {
+        Transport::Unix(self.socket_path.clone())
+    }
+}
+
+impl Drop for SimServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl KeyboardBackend for Simulator {
+    async fn get_status(&self) -> Result<GetStatusReply, Status> {
+        let mut state = self.state.lock().unwrap();
+        state.begin()?;
+        let connected_keyboard = state.connected.map(|index| ConnectedKeyboard {
+            friendly_name: state.keyboards[index].friendly_name.clone(),
+            firmware_version: "simulated".to_string(),
+            current_layer: state.layers.last().copied().unwrap_or(0),
+        });
+        Ok(GetStatusReply {
+            keymapp_version: format!("kontroll-sim {}", env!("CARGO_PKG_VERSION")),
+            connected_keyboard,
+        })
+    }
+
+    async fn get_keyboards(&self) -> Result<GetKeyboardsReply, Status> {
+        let mut state = self.state.lock().unwrap();
+        state.begin()?;
+        Ok(GetKeyboardsReply {
+            keyboards: state.keyboards.clone(),
+        })
+    }
+
+    async fn connect_keyboard(
+        &self,
+        request: ConnectKeyboardRequest,
+    ) -> Result<ConnectKeyboardReply, Status> {
+        let success = self
+            .update(|state| usize::try_from(request.id).is_ok_and(|index| state.connect(index)))?;
+        Ok(ConnectKeyboardReply { success })
+    }
+
+    async fn connect_any_keyboard(&self) -> Result<ConnectKeyboardReply, Status> {
+        let success = self.update(|state| state.connect(0))?;
+        Ok(ConnectKeyboardReply { success })
+    }
+
+    async fn disconnect_keyboard(&self) -> Result<DisconnectKeyboardReply, Status> {
+        let success = self.update(|state| state.disconnect())?;
+        Ok(DisconnectKeyboardReply { success })
+    }
+
+    async fn set_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let success = self.update(|state| {
+            let success = state.connected.is_some() && request.layer >= 0;
+            if success {
+                state.layers.retain(|&layer| layer != request.layer);
+                state.layers.push(request.layer);
+            }
+            success
+        })?;
+        Ok(SetLayerReply { success })
+    }
+
+    async fn unset_layer(&self, request: SetLayerRequest) -> Result<SetLayerReply, Status> {
+        let success = self.update(|state| {
+            let position = state
+                .layers
+                .iter()
+                .position(|&layer| layer == request.layer);
+            if let Some(position) = position {
+                state.layers.remove(position);
+            }
+            state.connected.is_some() && position.is_some()
+        })?;
+        Ok(SetLayerReply { success })
+    }
+
+    async fn set_rgb_led(&self, request: SetRgbLedRequest) -> Result<SetRgbLedReply, Status> {
+        let color = (request.red as u8, request.green as u8, request.blue as u8);
+        let success = self.update(|state| {
+            let connected = state.connected.is_some();
+            match usize::try_from(request.led)
+                .ok()
+                .and_then(|led| state.rgb_leds.get_mut(led))
+            {
+                Some(led) if connected => {
+                    *led = color;
+                    true
+                }
+                _ => false,
+            }
+        })?;
+        Ok(SetRgbLedReply { success })
+    }
+
+    async fn set_rgb_all(&self, request: SetRgbAllRequest) -> Result<SetRgbAllReply, Status> {
+        let color = (request.red as u8, request.green as u8, request.blue as u8);
+        let success = self.update(|state| {
+            let success = state.connected.is_some() && !state.rgb_leds.is_empty();
+            if success {
+                state.rgb_leds.fill(color);
+            }
+            success
+        })?;
+        Ok(SetRgbAllReply { success })
+    }
+
+    async fn set_status_led(
+        &self,
+        request: SetStatusLedRequest,
+    ) -> Result<SetStatusLedReply, Status> {
+        let success = self.update(|state| {
+            let connected = state.connected.is_some();
+            match usize::try_from(request.led)
+                .ok()
+                .and_then(|led| state.status_leds.get_mut(led))
+            {
+                Some(led) if connected => {
+                    *led = request.on;
+                    true
+                }
+                _ => false,
+            }
+        })?;
+        Ok(SetStatusLedReply { success })
+    }
+
+    async fn increase_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let success = self.update(|state| {
+            let success = state.connected.is_some() && state.brightness < u8::MAX;
+            if success {
+                state.brightness = state.brightness.saturating_add(BRIGHTNESS_STEP);
+            }
+            success
+        })?;
+        Ok(BrightnessUpdateReply { success })
+    }
+
+    async fn decrease_brightness(&self) -> Result<BrightnessUpdateReply, Status> {
+        let success = self.update(|state| {
+            let success = state.connected.is_some() && state.brightness > 0;
+            if success {
+                state.brightness = state.brightness.saturating_sub(BRIGHTNESS_STEP);
+            }
+            success
+        })?;
+        Ok(BrightnessUpdateReply { success })
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+use crate::api::ApiError;
+
+/// A bidirectional stream Keymapp's API can be spoken over.
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+type ConnectFuture = Pin<Box<dyn Future<Output = io::Result<Box<dyn Io>>> + Send>>;
+type Connector = Arc<dyn Fn() -> ConnectFuture + Send + Sync>;
+
+/// How Kontroll reaches Keymapp's API.
+///
+/// Transports can be parsed from `unix:///path/to/keymapp.sock` and `tcp://host:port`.
+#[derive(Clone)]
+pub enum Transport {
+    /// A Unix domain socket, used by Keymapp on Linux and macOS.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// A TCP connection to `host:port`, used by Keymapp on Windows.
+    Tcp(String),
+    /// A custom connector, called every time a connection needs to be established.
+    Custom(Connector),
+}
+
+impl Transport {
+    /// Creates a transport from a function opening a stream, such as one end of an in-memory
+    /// duplex stream.
+    pub fn custom<F, Fut, S>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<S>> + Send + 'static,
+        S: Io + 'static,
+    {
+        Transport::Custom(Arc::new(move || {
+            let connecting = connect();
+            Box::pin(async move { Ok(Box::new(connecting.await?) as Box<dyn Io>) })
+        }))
+    }
+
+    /// Returns the transport Keymapp listens on by default on this platform. On Unix, the
+    /// socket path can be set with `port` or the `KEYMAPP_SOCKET` environment variable, on
+    /// Windows the port can be set with `port` or the `KEYMAPP_PORT` environment variable.
+    #[cfg(unix)]
+    pub fn platform_default(port: Option<String>) -> Result<Self, ApiError> {
+        // Get socket path from the supplied path provided, or environment variable or set a default
+        let socket_path = match port {
+            Some(p) => PathBuf::from(p),
+            None => match std::env::var("KEYMAPP_SOCKET") {
+                Ok(p) => PathBuf::from(p),
+                Err(_) => {
+                    let dirs = match directories::BaseDirs::new() {
+                        Some(dirs) => dirs,
+                        None => return Err(ApiError::ConfigDirNotFound),
+                    };
+                    let mut p = dirs.config_dir().join(".keymapp/").join("keymapp.sock");
+                    if !p.exists() {
+                        // On MacOS, with the Keymapp app store version, the socket is sandboxed and
+                        // located in the app's container directory.
+                        p = dirs.home_dir().join("Library/Containers/io.zsa.keymapp/Data/Library/Application Support/.keymapp/keymapp.sock");
+                    }
+                    p
+                }
+            },
+        };
+        Ok(Transport::Unix(socket_path))
+    }
+
+    #[cfg(not(unix))]
+    pub fn platform_default(port: Option<String>) -> Result<Self, ApiError> {
+        // Get port number from the supplied path provided, or environment variable or set a default
+        let port =
+            port.unwrap_or_else(|| std::env::var("KEYMAPP_PORT").unwrap_or("50051".to_string()));
+        Ok(Transport::Tcp(format!("localhost:{}", port)))
+    }
+
+    /// Opens a channel to Keymapp over this transport.
+    pub(crate) async fn connect(&self) -> Result<Channel, ApiError> {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(socket_path) => {
+                if !socket_path.exists() {
+                    return Err(ApiError::SocketNotFound(socket_path.clone()));
+                }
+                let socket_path = socket_path.clone();
+                placeholder_endpoint()?
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        tokio::net::UnixStream::connect(socket_path.clone())
+                    }))
+                    .await
+                    .map_err(ApiError::Connect)
+            }
+            Transport::Tcp(addr) => Endpoint::from_shared(format!("http://{}", addr))
+                .map_err(ApiError::InvalidEndpoint)?
+                .connect()
+                .await
+                .map_err(ApiError::Connect),
+            Transport::Custom(connector) => {
+                let connector = connector.clone();
+                placeholder_endpoint()?
+                    .connect_with_connector(service_fn(move |_: Uri| connector()))
+                    .await
+                    .map_err(ApiError::Connect)
+            }
+        }
+    }
+}
+
+// Connectors ignore the endpoint's address, but tonic still requires a valid one.
+fn placeholder_endpoint() -> Result<Endpoint, ApiError> {
+    Endpoint::try_from("http://[::]:50051").map_err(ApiError::InvalidEndpoint)
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(path) => f.debug_tuple("Unix").field(path).finish(),
+            Transport::Tcp(addr) => f.debug_tuple("Tcp").field(addr).finish(),
+            Transport::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(path) => write!(f, "unix://{}", path.display()),
+            Transport::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Transport::Custom(_) => write!(f, "custom"),
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            #[cfg(unix)]
+            return match path {
+                "" => Err("missing socket path in unix endpoint".to_string()),
+                path => Ok(Transport::Unix(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!(
+                "unix sockets are not supported on this platform: {}",
+                path
+            ));
+        }
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            return match addr.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    Ok(Transport::Tcp(addr.to_string()))
+                }
+                _ => Err(format!("expected tcp://host:port, got {}", s)),
+            };
+        }
+        Err(format!(
+            "unsupported endpoint {}, expected unix:///path or tcp://host:port",
+            s
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix_endpoint() {
+        match "unix:///tmp/keymapp.sock".parse::<Transport>().unwrap() {
+            Transport::Unix(path) => assert_eq!(path, PathBuf::from("/tmp/keymapp.sock")),
+            t => panic!("unexpected transport {:?}", t),
+        }
+    }
+
+    #[test]
+    fn parse_tcp_endpoint() {
+        match "tcp://192.168.1.20:50051".parse::<Transport>().unwrap() {
+            Transport::Tcp(addr) => assert_eq!(addr, "192.168.1.20:50051"),
+            t => panic!("unexpected transport {:?}", t),
+        }
+    }
+
+    #[test]
+    fn reject_invalid_endpoints() {
+        assert!("tcp://localhost".parse::<Transport>().is_err());
+        assert!("tcp://:50051".parse::<Transport>().is_err());
+        assert!("http://localhost:50051".parse::<Transport>().is_err());
+        assert!("unix://".parse::<Transport>().is_err());
+    }
+}
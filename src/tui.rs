@@ -0,0 +1,126 @@
+//! Draws the simulated keyboard in the terminal with 24-bit ANSI colors.
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+
+use kontroll::sim::{Simulator, Snapshot};
+
+const CLEAR: &str = "\x1b[H\x1b[2J";
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+const RESET: &str = "\x1b[0m";
+
+/// Redraws the keyboard every time the simulator's state changes, until dropped.
+pub async fn run(sim: &Simulator, socket: &Path) -> io::Result<()> {
+    let mut changes = sim.subscribe();
+    loop {
+        let frame = render(&sim.snapshot(), socket);
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}{}{}", HIDE_CURSOR, CLEAR, frame)?;
+        stdout.flush()?;
+        drop(stdout);
+        if changes.changed().await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Gives the cursor back once the visualizer stops.
+pub fn restore() {
+    print!("{}", SHOW_CURSOR);
+    let _ = io::stdout().flush();
+}
+
+fn led(color: Option<(u8, u8, u8)>) -> String {
+    match color {
+        Some((r, g, b)) => format!("\x1b[48;2;{};{};{}m    {}", r, g, b, RESET),
+        None => "    ".to_string(),
+    }
+}
+
+/// Renders a frame showing the LEDs, status LEDs, current layer and brightness.
+pub fn render(snapshot: &Snapshot, socket: &Path) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Simulating a {} on {}, press Ctrl+C to stop\n",
+        snapshot.model.friendly_name(),
+        socket.display()
+    );
+    if !snapshot.connected {
+        out.push_str("No keyboard connected\n");
+        return out;
+    }
+
+    let status: Vec<&str> = snapshot
+        .status_leds
+        .iter()
+        .map(|&on| {
+            if on {
+                "\x1b[38;2;255;200;0m●\x1b[0m"
+            } else {
+                "\x1b[38;2;80;80;80m○\x1b[0m"
+            }
+        })
+        .collect();
+    let _ = writeln!(out, "Status LEDs: {}\n", status.join(" "));
+
+    let keyboard = snapshot.model;
+    if !keyboard.has_rgb() {
+        out.push_str("No RGB LEDs\n\n");
+    }
+    let color = |led: usize| snapshot.rgb_leds.get(led).copied();
+    for row in 0..keyboard.rows() {
+        for col in 0..keyboard.cols() {
+            if col == keyboard.cols() / 2 {
+                out.push_str("      ");
+            }
+            out.push_str(&led(keyboard.led_at(row, col).and_then(color)));
+            out.push(' ');
+        }
+        out.push_str("\n\n");
+    }
+
+    let filled = snapshot.brightness as usize * 20 / u8::MAX as usize;
+    let _ = writeln!(out, "Layer:      {}", snapshot.layer);
+    let _ = writeln!(
+        out,
+        "Brightness: [{}{}] {}",
+        "#".repeat(filled),
+        " ".repeat(20 - filled),
+        snapshot.brightness
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kontroll::keyboard;
+
+    #[test]
+    fn render_voyager() {
+        let sim = Simulator::new();
+        sim.set_connected_index(0);
+        let mut snapshot = sim.snapshot();
+        snapshot.rgb_leds[12] = (255, 0, 0);
+        snapshot.status_leds[1] = true;
+        snapshot.layer = 3;
+
+        let frame = render(&snapshot, Path::new("/tmp/keymapp.sock"));
+        assert!(frame.contains("\x1b[48;2;255;0;0m"));
+        assert!(frame.contains("\x1b[38;2;255;200;0m●"));
+        assert!(frame.contains("Layer:      3"));
+        assert!(frame.contains("Brightness: [##########          ] 128"));
+    }
+
+    #[test]
+    fn render_moonlander() {
+        let sim = Simulator::with_model(&keyboard::MOONLANDER);
+        sim.set_connected_index(0);
+        let mut snapshot = sim.snapshot();
+        snapshot.rgb_leds[71] = (0, 0, 255);
+        let frame = render(&snapshot, Path::new("/tmp/keymapp.sock"));
+        assert!(frame.contains("\x1b[48;2;0;0;255m"));
+    }
+}
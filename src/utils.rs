@@ -1,38 +1,21 @@
+pub use crate::color::Color;
+use crate::color::ParseColorError;
+use crate::keyboard;
+
 /// Converts a hex color to a tuple of (r, g, b)
 pub fn hex_to_rgb(hex: &str) -> Result<(u8, u8, u8), Box<dyn std::error::Error>> {
-    let hex = hex.trim_start_matches("#");
-    let r = u8::from_str_radix(&hex[0..2], 16)?;
-    let g = u8::from_str_radix(&hex[2..4], 16)?;
-    let b = u8::from_str_radix(&hex[4..6], 16)?;
-
-    Ok((r, g, b))
+    let color = Color::from_hex(hex.trim()).ok_or_else(|| ParseColorError::new(hex))?;
+    Ok(color.rgb())
 }
 
-// Converts a position on the Voyager to the corresponding key index
+/// Converts a position on the Voyager to the corresponding key index, 60 where there is no key.
+///
+/// Rows 0 to 3 hold the keys of both halves, 12 columns wide, and row 4 the thumb keys in
+/// columns 4 to 7. The layouts of all models are in [`crate::keyboard`].
 pub fn pos_to_voyager(x: u16, y: u16) -> usize {
-    // 0,  5   is left 1st row
-    // 6,  11  is left 2st row
-    // 12, 17  is left 3st row
-    // 18, 23  is left 4st row
-
-    // 24, 25 is left thumb keys
-    // 50, 51 is right thumb keys
-
-    // 26, 31  is right 1st row
-    // 32, 37  is right 2st row
-    // 38, 43  is right 3st row
-    // 44, 49  is right 4st row
-
-    #[rustfmt::skip]
-    let voyager_layout: [[usize; 12]; 5] = [
-        [ 0,  1,  2,  3,  4,  5,        26, 27, 28, 29, 30, 31],
-        [ 6,  7,  8,  9, 10, 11,        32, 33, 34, 35, 36, 37],
-        [12, 13, 14, 15, 16, 17,        38, 39, 40, 41, 42, 43],
-        [18, 19, 20, 21, 22, 23,        44, 45, 46, 47, 48, 49],
-
-        [60, 60, 60, 60, 24, 25,        50, 51, 60, 60, 60, 60]
-    ];
-    voyager_layout[y as usize][x as usize]
+    keyboard::VOYAGER
+        .led_at(y as usize, x as usize)
+        .unwrap_or(60)
 }
 
 #[cfg(test)]
@@ -56,4 +39,11 @@ mod tests {
         assert_eq!(g, 222);
         assert_eq!(b, 206);
     }
+
+    #[test]
+    fn hex_to_rgb_rejects_short_input() {
+        assert!(hex_to_rgb("#ff").is_err());
+        assert!(hex_to_rgb("red").is_err());
+        assert_eq!(hex_to_rgb("#fff").unwrap(), (255, 255, 255));
+    }
 }
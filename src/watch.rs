@@ -0,0 +1,162 @@
+//! Changes of Keymapp's status, reported by [`Kontroll::watch_status`](crate::Kontroll::watch_status).
+use std::fmt;
+
+use crate::api::Status;
+
+/// A change between two polls of Keymapp's status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(tag = "event", rename_all = "snake_case")
+)]
+pub enum StatusEvent {
+    /// The connected keyboard switched to another layer.
+    LayerChanged { previous: i32, layer: i32 },
+    /// A keyboard was connected, also reported for the keyboard connected when watching starts.
+    KeyboardConnected {
+        friendly_name: String,
+        firmware_version: String,
+        layer: i32,
+    },
+    /// The keyboard was disconnected.
+    KeyboardDisconnected { friendly_name: String },
+    /// Keymapp was updated, usually noticed after it restarts.
+    KeymappVersionChanged { previous: String, version: String },
+}
+
+impl fmt::Display for StatusEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatusEvent::LayerChanged { previous, layer } => {
+                write!(f, "Layer changed from {} to {}", previous, layer)
+            }
+            StatusEvent::KeyboardConnected {
+                friendly_name,
+                firmware_version,
+                layer,
+            } => write!(
+                f,
+                "Keyboard connected: {} (firmware {}) on layer {}",
+                friendly_name, firmware_version, layer
+            ),
+            StatusEvent::KeyboardDisconnected { friendly_name } => {
+                write!(f, "Keyboard disconnected: {}", friendly_name)
+            }
+            StatusEvent::KeymappVersionChanged { previous, version } => {
+                write!(
+                    f,
+                    "Keymapp version changed from {} to {}",
+                    previous, version
+                )
+            }
+        }
+    }
+}
+
+/// Returns the events leading from the previous status to the current one, `None` for the
+/// first poll.
+pub(crate) fn events(previous: Option<&Status>, current: &Status) -> Vec<StatusEvent> {
+    let mut events = Vec::new();
+    if let Some(previous) = previous {
+        if previous.keymapp_version() != current.keymapp_version() {
+            events.push(StatusEvent::KeymappVersionChanged {
+                previous: previous.keymapp_version().to_string(),
+                version: current.keymapp_version().to_string(),
+            });
+        }
+    }
+
+    let before = previous.and_then(Status::keyboard);
+    match (before, current.keyboard()) {
+        // Another keyboard replacing the previous one is a disconnection then a connection.
+        (Some(before), Some(after))
+            if before.friendly_name() == after.friendly_name()
+                && before.firmware_version() == after.firmware_version() =>
+        {
+            if before.current_layer() != after.current_layer() {
+                events.push(StatusEvent::LayerChanged {
+                    previous: before.current_layer(),
+                    layer: after.current_layer(),
+                });
+            }
+        }
+        (before, after) => {
+            if let Some(before) = before {
+                events.push(StatusEvent::KeyboardDisconnected {
+                    friendly_name: before.friendly_name().to_string(),
+                });
+            }
+            if let Some(after) = after {
+                events.push(StatusEvent::KeyboardConnected {
+                    friendly_name: after.friendly_name().to_string(),
+                    firmware_version: after.firmware_version().to_string(),
+                    layer: after.current_layer(),
+                });
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::keymapp::{ConnectedKeyboard, GetStatusReply};
+
+    fn status(version: &str, keyboard: Option<(&str, i32)>) -> Status {
+        Status::from(GetStatusReply {
+            keymapp_version: version.to_string(),
+            connected_keyboard: keyboard.map(|(name, layer)| ConnectedKeyboard {
+                friendly_name: name.to_string(),
+                firmware_version: "1.0".to_string(),
+                current_layer: layer,
+            }),
+        })
+    }
+
+    #[test]
+    fn first_poll_reports_the_keyboard() {
+        let current = status("1.0", Some(("Voyager", 0)));
+        assert_eq!(
+            events(None, &current),
+            vec![StatusEvent::KeyboardConnected {
+                friendly_name: "Voyager".to_string(),
+                firmware_version: "1.0".to_string(),
+                layer: 0,
+            }]
+        );
+        assert_eq!(events(None, &status("1.0", None)), vec![]);
+    }
+
+    #[test]
+    fn deltas() {
+        let before = status("1.0", Some(("Voyager", 0)));
+        assert_eq!(events(Some(&before), &before), vec![]);
+        assert_eq!(
+            events(Some(&before), &status("1.1", Some(("Voyager", 2)))),
+            vec![
+                StatusEvent::KeymappVersionChanged {
+                    previous: "1.0".to_string(),
+                    version: "1.1".to_string(),
+                },
+                StatusEvent::LayerChanged {
+                    previous: 0,
+                    layer: 2
+                },
+            ]
+        );
+        assert_eq!(
+            events(Some(&before), &status("1.0", Some(("Moonlander", 0))))[0],
+            StatusEvent::KeyboardDisconnected {
+                friendly_name: "Voyager".to_string()
+            }
+        );
+        assert_eq!(
+            events(Some(&before), &status("1.0", None)),
+            vec![StatusEvent::KeyboardDisconnected {
+                friendly_name: "Voyager".to_string()
+            }]
+        );
+    }
+}
@@ -1,9 +1,842 @@
-extern crate kontroll;
-#[path = "./stub.rs"]
-mod stub;
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use assert_cmd::Command;
+use kontroll::api::keymapp::SetLayerRequest;
+use kontroll::backend::Call;
+use kontroll::session::Reply;
+use kontroll::sim::{SimServer, Simulator, DEFAULT_BRIGHTNESS};
+use predicates::str::contains;
+
+fn setup(connected: bool) -> (Simulator, SimServer) {
+    let sim = Simulator::new();
+    if connected {
+        sim.set_connected_index(0);
+    }
+    let server = sim.start().unwrap();
+    (sim, server)
+}
+
+fn kontroll(server: &SimServer) -> Command {
+    let mut cmd = Command::cargo_bin("kontroll").unwrap();
+    cmd.arg("--port").arg(server.socket_path());
+    cmd
+}
+
+#[test]
+fn status() {
+    let (_sim, server) = setup(true);
+    kontroll(&server)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(contains("Connected keyboard:\tVoyager"));
+}
+
+#[test]
+fn status_json() {
+    let (_sim, server) = setup(false);
+    kontroll(&server)
+        .args(["status", "--json"])
+        .assert()
+        .success()
+        .stdout(contains("\"keyboard\": null"));
+}
+
+#[test]
+fn list() {
+    let (_sim, server) = setup(true);
+    kontroll(&server)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(contains("0: Voyager (connected)"));
+}
+
+#[test]
+fn connect() {
+    let (sim, server) = setup(false);
+    kontroll(&server)
+        .args(["connect", "--index", "0"])
+        .assert()
+        .success();
+    assert!(sim.connected_keyboard().unwrap().is_connected);
+
+    kontroll(&server)
+        .args(["connect", "--index", "3"])
+        .assert()
+        .code(7);
+}
+
+#[test]
+fn connect_any() {
+    let (sim, server) = setup(false);
+    kontroll(&server).arg("connect-any").assert().success();
+    assert_eq!(sim.connected_keyboard().unwrap().friendly_name, "Voyager");
+}
+
+#[test]
+fn disconnect() {
+    let (sim, server) = setup(true);
+    kontroll(&server).arg("disconnect").assert().success();
+    assert!(sim.connected_keyboard().is_none());
+    kontroll(&server).arg("disconnect").assert().code(7);
+}
+
+#[test]
+fn set_layer() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-layer", "--index", "2"])
+        .assert()
+        .success()
+        .stdout(contains("Layer set to 2"));
+    assert_eq!(sim.current_layer(), 2);
+}
+
+#[test]
+fn set_layer_without_keyboard() {
+    let (_sim, server) = setup(false);
+    kontroll(&server)
+        .args(["set-layer", "--index", "2"])
+        .assert()
+        .code(7);
+    kontroll(&server)
+        .args(["--no-strict", "set-layer", "--index", "2"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn unset_layer() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-layer", "--index", "2"])
+        .assert()
+        .success();
+    kontroll(&server)
+        .args(["unset-layer", "--index", "2"])
+        .assert()
+        .success();
+    assert!(sim.layers().is_empty());
+}
+
+#[test]
+fn set_rgb() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-rgb", "--led", "12", "--color", "#ff8000"])
+        .assert()
+        .success();
+    assert_eq!(sim.rgb_led(12), Some((255, 128, 0)));
+}
+
+#[test]
+fn set_rgb_invalid_color() {
+    let (_sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-rgb", "--led", "12", "--color", "notacolor"])
+        .assert()
+        .code(2)
+        .stderr(contains("notacolor is not a valid color"));
+    kontroll(&server)
+        .args(["set-rgb", "--led", "12", "--color", "#ff"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn set_rgb_color_formats() {
+    let (sim, server) = setup(true);
+    for (color, rgb) in [
+        ("orange", (255, 165, 0)),
+        ("#fff", (255, 255, 255)),
+        ("rgb(10, 20, 30)", (10, 20, 30)),
+        ("hsl(240, 100%, 50%)", (0, 0, 255)),
+        ("off", (0, 0, 0)),
+    ] {
+        kontroll(&server)
+            .args(["set-rgb", "--led", "12", "--color", color])
+            .assert()
+            .success();
+        assert_eq!(sim.rgb_led(12), Some(rgb), "{}", color);
+    }
+}
+
+#[test]
+fn set_rgb_all() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-rgb-all", "--color", "00ff00"])
+        .assert()
+        .success();
+    assert_eq!(sim.rgb_led(0), Some((0, 255, 0)));
+    assert_eq!(sim.rgb_led(51), Some((0, 255, 0)));
+}
+
+#[test]
+fn set_rgb_selectors() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-rgb", "--led", "left-thumbs,r0c11", "--color", "red"])
+        .assert()
+        .success()
+        .stdout(contains("3 LEDs set to color #ff0000"));
+    assert_eq!(sim.rgb_led(24), Some((255, 0, 0)));
+    assert_eq!(sim.rgb_led(25), Some((255, 0, 0)));
+    assert_eq!(sim.rgb_led(31), Some((255, 0, 0)));
+    assert_eq!(sim.rgb_led(50), Some((0, 0, 0)));
+
+    kontroll(&server)
+        .args(["set-rgb", "--led", "r4c0", "--color", "red"])
+        .assert()
+        .code(2)
+        .stderr(contains("r4c0 selects no LED on the Voyager"));
+
+    let (_sim, server) = setup(false);
+    kontroll(&server)
+        .args(["set-rgb", "--led", "thumbs", "--color", "red"])
+        .assert()
+        .code(2)
+        .stderr(contains(
+            "Selecting LEDs by position needs a connected keyboard",
+        ));
+}
+
+#[test]
+fn set_rgb_keys() {
+    let (sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let layout = dir.path().join("keymap.json");
+    let layer = |keys: &[(usize, &str)]| {
+        let mut layer = vec!["KC_TRNS"; 52];
+        for &(position, keycode) in keys {
+            layer[position] = keycode;
+        }
+        format!("{:?}", layer)
+    };
+    std::fs::write(
+        &layout,
+        format!(
+            r#"{{"keyboard": "zsa/voyager", "layout": "LAYOUT", "layers": [{}, {}, {}]}}"#,
+            layer(&[(0, "KC_ESC")]),
+            layer(&[]),
+            layer(&[(12, "KC_LSFT"), (49, "LCTL_T(KC_SPC)"), (50, "KC_A")]),
+        ),
+    )
+    .unwrap();
+
+    kontroll(&server)
+        .args(["set-rgb", "--key", "KC_ESC", "--color", "red", "--layout"])
+        .arg(&layout)
+        .assert()
+        .success()
+        .stdout(contains("LED 0 set to color #ff0000"));
+    assert_eq!(sim.rgb_led(0), Some((255, 0, 0)));
+
+    // The 13th key is the first of the left half's second row, the 50th the second left thumb.
+    kontroll(&server)
+        .args(["set-rgb", "--layer-keys", "2:modifiers", "--color", "blue"])
+        .arg("--layout")
+        .arg(&layout)
+        .assert()
+        .success()
+        .stdout(contains("2 LEDs set to color #0000ff"));
+    assert_eq!(sim.rgb_led(6), Some((0, 0, 255)));
+    assert_eq!(sim.rgb_led(25), Some((0, 0, 255)));
+    assert_eq!(sim.rgb_led(50), Some((0, 0, 0)));
+
+    kontroll(&server)
+        .args(["set-rgb", "--key", "KC_F1", "--color", "red", "--layout"])
+        .arg(&layout)
+        .assert()
+        .code(2)
+        .stderr(contains("KC_F1 isn't on layer 0"));
+    kontroll(&server)
+        .args(["set-rgb", "--key", "KC_ESC", "--color", "red"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn gradient() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args([
+            "gradient",
+            "--from",
+            "red",
+            "--via",
+            "lime",
+            "--to",
+            "blue",
+            "--leds",
+            "4-0",
+            "--interpolation",
+            "linear",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("Gradient from #ff0000 to #0000ff set on 5 LEDs"));
+    assert_eq!(sim.rgb_led(4), Some((255, 0, 0)));
+    assert_eq!(sim.rgb_led(3), Some((128, 128, 0)));
+    assert_eq!(sim.rgb_led(2), Some((0, 255, 0)));
+    assert_eq!(sim.rgb_led(0), Some((0, 0, 255)));
+    assert_eq!(sim.rgb_led(5), Some((0, 0, 0)));
+
+    kontroll(&server)
+        .args(["gradient", "--from", "red", "--to", "blue", "--leds", "1,x"])
+        .assert()
+        .code(2)
+        .stderr(contains("x is not a valid LED"));
+}
+
+#[test]
+fn restore_rgb_leds() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-rgb-all", "--color", "00ff00"])
+        .assert()
+        .success();
+    kontroll(&server).arg("restore-rgb-leds").assert().success();
+    assert_eq!(sim.rgb_led(0), Some((0, 0, 0)));
+}
+
+#[test]
+fn set_status_led() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["set-status-led", "--led", "1"])
+        .assert()
+        .success()
+        .stdout(contains("Status LED 1 turned on"));
+    assert_eq!(sim.status_led(1), Some(true));
+
+    kontroll(&server)
+        .args(["set-status-led", "--led", "1", "--off"])
+        .assert()
+        .success();
+    assert_eq!(sim.status_led(1), Some(false));
+}
+
+#[test]
+fn restore_status_leds() {
+    let (_sim, server) = setup(true);
+    kontroll(&server)
+        .arg("restore-status-leds")
+        .assert()
+        .success();
+}
+
+#[test]
+fn increase_brightness() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["increase-brightness", "--steps", "2"])
+        .assert()
+        .success();
+    assert!(sim.brightness() > DEFAULT_BRIGHTNESS);
+}
+
+#[test]
+fn decrease_brightness() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .arg("decrease-brightness")
+        .assert()
+        .success();
+    assert!(sim.brightness() < DEFAULT_BRIGHTNESS);
+}
+
+#[test]
+fn failed_request() {
+    let (sim, server) = setup(true);
+    sim.fail_next_request(true);
+    kontroll(&server)
+        .arg("status")
+        .assert()
+        .code(6)
+        .stderr(contains("simulated failure"));
+    kontroll(&server).arg("status").assert().success();
+}
+
+#[test]
+fn missing_socket() {
+    let (_sim, server) = setup(true);
+    let socket_path = server.socket_path().to_path_buf();
+    drop(server);
+    Command::cargo_bin("kontroll")
+        .unwrap()
+        .arg("--port")
+        .arg(socket_path)
+        .arg("status")
+        .assert()
+        .code(3);
+}
+
+/// Runs a kontroll command serving a socket in the background, killed when dropped.
+struct Background(std::process::Child);
+
+impl Background {
+    fn spawn(args: &[&str], socket_path: &Path) -> Self {
+        let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("kontroll"))
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        for _ in 0..100 {
+            if socket_path.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Self(child)
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn sim() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("sim.sock");
+    let socket = socket_path.to_str().unwrap();
+    let _sim = Background::spawn(
+        &["sim", "--model", "moonlander", "--socket", socket],
+        &socket_path,
+    );
+
+    Command::cargo_bin("kontroll")
+        .unwrap()
+        .args(["--port", socket])
+        .args(["set-rgb", "--led", "71", "--color", "#0000ff"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn proxy() {
+    let (sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("proxy.sock");
+    let socket = socket_path.to_str().unwrap();
+    let session_path = dir.path().join("session.jsonl");
+    let _proxy = Background::spawn(
+        &[
+            "proxy",
+            "--listen",
+            socket,
+            "--upstream",
+            server.socket_path().to_str().unwrap(),
+            "--record",
+            session_path.to_str().unwrap(),
+        ],
+        &socket_path,
+    );
+
+    Command::cargo_bin("kontroll")
+        .unwrap()
+        .args(["--port", socket, "set-layer", "--index", "3"])
+        .assert()
+        .success();
+    assert_eq!(sim.current_layer(), 3);
+
+    let session = std::fs::File::open(session_path).unwrap();
+    let entries = kontroll::session::read(std::io::BufReader::new(session)).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0].request,
+        Call::SetLayer(SetLayerRequest { layer: 3 })
+    );
+    assert_eq!(entries[0].response, Reply::Success { success: true });
+}
+
+fn spawn_daemon(server: &SimServer, socket_path: &Path) -> Background {
+    Background::spawn(
+        &[
+            "daemon",
+            "--listen",
+            socket_path.to_str().unwrap(),
+            "--upstream",
+            server.socket_path().to_str().unwrap(),
+        ],
+        socket_path,
+    )
+}
+
+#[test]
+fn daemon() {
+    let (sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("kontroll.sock");
+    let socket = socket_path.to_str().unwrap();
+    let _daemon = spawn_daemon(&server, &socket_path);
+
+    for (led, color) in [("1", "#ff0000"), ("2", "#00ff00")] {
+        Command::cargo_bin("kontroll")
+            .unwrap()
+            .args(["--port", socket, "set-rgb", "--led", led, "--color", color])
+            .assert()
+            .success();
+    }
+    assert_eq!(sim.rgb_led(1), Some((255, 0, 0)));
+    assert_eq!(sim.rgb_led(2), Some((0, 255, 0)));
+
+    // A call failing upstream doesn't break the shared connection.
+    sim.fail_next_request(true);
+    Command::cargo_bin("kontroll")
+        .unwrap()
+        .args(["--port", socket, "set-layer", "--index", "2"])
+        .assert()
+        .success();
+    assert_eq!(sim.current_layer(), 2);
+}
+
+#[test]
+fn daemon_led_layers() {
+    let (sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("kontroll.sock");
+    let socket = socket_path.to_str().unwrap();
+    let _daemon = spawn_daemon(&server, &socket_path);
+    let run = |layer: &str, priority: &str, args: &[&str]| {
+        Command::cargo_bin("kontroll")
+            .unwrap()
+            .args([
+                "--port",
+                socket,
+                "--led-layer",
+                layer,
+                "--priority",
+                priority,
+            ])
+            .args(args)
+            .assert()
+            .success();
+    };
+
+    run("clock", "0", &["set-rgb-all", "--color", "#0000ff"]);
+    run(
+        "ci-status",
+        "10",
+        &["set-rgb", "--led", "1", "--color", "#ff0000"],
+    );
+    run(
+        "clock",
+        "0",
+        &["set-rgb", "--led", "1", "--color", "#00ff00"],
+    );
+    assert_eq!(sim.rgb_led(1), Some((255, 0, 0)));
+    assert_eq!(sim.rgb_led(2), Some((0, 0, 255)));
+
+    run("ci-status", "10", &["restore-rgb-leds"]);
+    assert_eq!(sim.rgb_led(1), Some((0, 255, 0)));
+    assert_eq!(sim.rgb_led(2), Some((0, 0, 255)));
+}
+
+#[test]
+fn watch() {
+    let (_sim, server) = setup(true);
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("kontroll"))
+        .arg("--port")
+        .arg(server.socket_path())
+        .args(["watch", "--json", "--interval", "20"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let _watch = Background(child);
+    let (sender, lines) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let next = || lines.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let connected = next();
+    assert!(connected.starts_with(r#"{"event":"keyboard_connected","friendly_name":"Voyager""#));
+    kontroll(&server)
+        .args(["set-layer", "--index", "2"])
+        .assert()
+        .success();
+    assert_eq!(
+        next(),
+        r#"{"event":"layer_changed","previous":0,"layer":2}"#
+    );
+}
+
+#[test]
+fn watch_zero_interval() {
+    let (_sim, server) = setup(true);
+    kontroll(&server)
+        .args(["watch", "--interval", "0"])
+        .assert()
+        .code(2)
+        .stderr(contains("0 is not in 1.."));
+}
+
+fn wait_for(path: &Path) -> String {
+    for _ in 0..100 {
+        // The shell creates the file before writing to it.
+        match std::fs::read_to_string(path) {
+            Ok(content) if !content.is_empty() => return content,
+            _ => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    panic!("{} was not written", path.display());
+}
+
+#[test]
+fn on_layer() {
+    let (_sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let entered = dir.path().join("entered.txt");
+    let left = dir.path().join("left.txt");
+    let hook = |path: &Path| {
+        format!(
+            "echo \"$KONTROLL_PREV_LAYER $KONTROLL_LAYER\" > {}",
+            path.display()
+        )
+    };
+    // The layer active when watching starts is entered.
+    let _hooks = Background::spawn(
+        &[
+            "--port",
+            server.socket_path().to_str().unwrap(),
+            "on-layer",
+            "--layer",
+            "0",
+            "--exec",
+            &hook(&entered),
+            "--leave",
+            &hook(&left),
+            "--interval",
+            "20",
+        ],
+        &entered,
+    );
+    assert_eq!(wait_for(&entered), " 0\n");
+
+    kontroll(&server)
+        .args(["set-layer", "--index", "2"])
+        .assert()
+        .success();
+    assert_eq!(wait_for(&left), "0 2\n");
+}
+
+#[test]
+fn on_layer_zero_interval() {
+    let (_sim, server) = setup(true);
+    kontroll(&server)
+        .args([
+            "on-layer",
+            "--layer",
+            "1",
+            "--exec",
+            "true",
+            "--interval",
+            "0",
+        ])
+        .assert()
+        .code(2)
+        .stderr(contains("0 is not in 1.."));
+}
+
+#[test]
+fn on_layer_config() {
+    let (_sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.json");
+    let ready = dir.path().join("ready");
+    let entered = dir.path().join("entered.txt");
+    std::fs::write(
+        &config,
+        serde_json::json!({
+            "layers": {
+                "0": {"on-enter": format!("touch {}", ready.display())},
+                "2": {"on-enter": format!("echo $KONTROLL_LAYER > {}", entered.display())},
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let _hooks = Background::spawn(
+        &[
+            "--port",
+            server.socket_path().to_str().unwrap(),
+            "on-layer",
+            "--config",
+            config.to_str().unwrap(),
+            "--interval",
+            "20",
+        ],
+        &ready,
+    );
+
+    kontroll(&server)
+        .args(["set-layer", "--index", "2"])
+        .assert()
+        .success();
+    assert_eq!(wait_for(&entered), "2\n");
+
+    std::fs::write(&config, r#"{"layers": {"2": {"on-entr": "true"}}}"#).unwrap();
+    kontroll(&server)
+        .args(["on-layer", "--config", config.to_str().unwrap()])
+        .assert()
+        .code(2)
+        .stderr(contains("unknown field `on-entr`"));
+}
+
+#[test]
+fn replay() {
+    let (sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let session_path = dir.path().join("session.jsonl");
+    std::fs::write(
+        &session_path,
+        concat!(
+            r#"{"timestamp_ms":1000,"request":{"method":"SetRgbAll","red":0,"green":0,"blue":255,"sustain":0},"response":{"success":true}}"#,
+            "\n",
+            r#"{"timestamp_ms":1100,"request":{"method":"GetStatus"},"response":{"success":true}}"#,
+            "\n",
+            r#"{"timestamp_ms":1200,"request":{"method":"SetLayer","layer":4},"response":{"success":true}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    kontroll(&server)
+        .args(["replay", "--speed", "10"])
+        .arg(&session_path)
+        .assert()
+        .success()
+        .stdout(contains("Replayed 2 calls"));
+    assert_eq!(sim.rgb_led(7), Some((0, 0, 255)));
+    assert_eq!(sim.current_layer(), 4);
+}
+
+#[test]
+fn replay_loop_in_batch() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .arg("batch")
+        .write_stdin("replay --loop session.jsonl\nset-layer --index 3\n")
+        .assert()
+        .code(2)
+        .stderr(contains("1: replay --loop can only be run on its own"))
+        .stdout(contains("2: Layer set to 3"));
+    assert_eq!(sim.current_layer(), 3);
+}
+
+#[test]
+fn push_layer_one_shot() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .args(["push-layer", "--index", "2"])
+        .assert()
+        .code(2)
+        .stderr(contains("can only be run within a batch or shell"));
+    assert_eq!(sim.current_layer(), 0);
+}
+
+#[test]
+fn batch_quoted_arguments() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .arg("batch")
+        .write_stdin(concat!(
+            "set-rgb-all --color rgb(10, 20, 30)\n",
+            "set-rgb --led 3 --color 'hsl(120, 100%, 50%)'\n",
+            "set-rgb --led \"4\" --color \"dark red\"\n",
+        ))
+        .assert()
+        .code(2)
+        .stdout(contains("1: All LEDs set to color #0a141e"))
+        .stdout(contains("2: LED 3 set to color #00ff00"))
+        .stderr(contains("3: invalid value 'dark red'"));
+    assert_eq!(sim.rgb_led(0), Some((10, 20, 30)));
+    assert_eq!(sim.rgb_led(3), Some((0, 255, 0)));
+}
+
+#[test]
+fn batch() {
+    let (sim, server) = setup(true);
+    kontroll(&server)
+        .arg("batch")
+        .write_stdin(concat!(
+            "# set a layer and light a key\n",
+            "push-layer --index 2\n",
+            "\n",
+            "set-rgb --led 3 --color #00ff00\n",
+            "set-rgb --led 3\n",
+            "push-layer --index 5\n",
+            "pop-layer\n",
+        ))
+        .assert()
+        .code(2)
+        .stdout(contains("2: Layer 2 pushed"))
+        .stdout(contains("4: LED 3 set to color #00ff00"))
+        .stdout(contains("7: Layer 5 popped"))
+        .stderr(contains(
+            "5: the following required arguments were not provided",
+        ));
+    assert_eq!(sim.rgb_led(3), Some((0, 255, 0)));
+    assert_eq!(sim.layers(), vec![2]);
+}
+
+#[test]
+fn batch_file() {
+    let (sim, server) = setup(true);
+    let dir = tempfile::tempdir().unwrap();
+    let batch_path = dir.path().join("commands.txt");
+    std::fs::write(
+        &batch_path,
+        "set-layer --index 1\nset-rgb-all --color 0000ff\n",
+    )
+    .unwrap();
+    kontroll(&server)
+        .arg("batch")
+        .arg(&batch_path)
+        .assert()
+        .success();
+    assert_eq!(sim.current_layer(), 1);
+    assert_eq!(sim.rgb_led(20), Some((0, 0, 255)));
+}
 
 #[test]
-fn list_keyboards() {
-    let keymapp = stub::Keymapp::default();
-    assert_eq!(1, 1);
+fn shell() {
+    let (sim, server) = setup(true);
+    sim.fail_next_request(true);
+    kontroll(&server)
+        .arg("shell")
+        .write_stdin(concat!(
+            "layer 2\n",
+            "layer 2\n",
+            "rgb 12 #ff0000\n",
+            "led 1 off\n",
+            "exit\n",
+            "layer 4\n",
+        ))
+        .assert()
+        .success()
+        .stdout(contains("Layer set to 2"))
+        .stdout(contains("LED 12 set to color #ff0000"))
+        .stdout(contains("Status LED 1 turned off"))
+        .stderr(contains("simulated failure"))
+        .stderr(contains("Reconnected to Keymapp"));
+    assert_eq!(sim.current_layer(), 2);
+    assert_eq!(sim.rgb_led(12), Some((255, 0, 0)));
 }